@@ -0,0 +1,347 @@
+//! Wire types of the blinds MQTT and HTTP API
+//!
+//! Payloads are JSON. Changes within one [`PROTOCOL_VERSION`] only add optional fields
+//! and new enum variants, so readers should ignore fields they don't know.
+//! States, command sources and switch actions added later parse as `Unknown`.
+//! Actions and motion modes the service doesn't know are rejected, so commands
+//! using new ones need a service of matching version
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Incremented when a payload changes in a way old readers can't parse
+///
+/// Sent as [`BlindsStatus::version`]
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Reported state of the blinds
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlindsState {
+    Open,
+    Partial,
+    Closed,
+    Opening,
+    Closing,
+    Other,
+    /// Serial port is being reopened
+    Disconnected,
+    /// State added by a newer service. Never sent by this version
+    #[serde(other)]
+    Unknown,
+}
+
+impl BlindsState {
+    /// States this version sends
+    pub const ALL: [BlindsState; 7] = [
+        BlindsState::Open,
+        BlindsState::Partial,
+        BlindsState::Closed,
+        BlindsState::Opening,
+        BlindsState::Closing,
+        BlindsState::Other,
+        BlindsState::Disconnected,
+    ];
+
+    /// Same as serialized name
+    pub fn label(&self) -> &'static str {
+        match self {
+            BlindsState::Open => "open",
+            BlindsState::Partial => "partial",
+            BlindsState::Closed => "closed",
+            BlindsState::Opening => "opening",
+            BlindsState::Closing => "closing",
+            BlindsState::Other => "other",
+            BlindsState::Disconnected => "disconnected",
+            BlindsState::Unknown => "unknown",
+        }
+    }
+}
+
+/// Speed and current limits of a motion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum MotionMode {
+    #[default]
+    Normal,
+    /// Reduced speed and current limits
+    Quiet,
+}
+
+/// Single motion of the blinds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum BlindsAction {
+    Open,
+    Close,
+    Toggle,
+    Partial {
+        open: f32,
+    },
+    Preset {
+        name: String,
+    },
+    SetPosition {
+        position: f32,
+    },
+    SetTilt {
+        tilt: f32,
+    },
+    /// Find end stops and save them to config
+    Calibrate,
+}
+
+impl BlindsAction {
+    /// Same as name of matching MQTT topic
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlindsAction::Open => "open",
+            BlindsAction::Close => "close",
+            BlindsAction::Toggle => "toggle",
+            BlindsAction::Partial { .. } => "partial",
+            BlindsAction::Preset { .. } => "preset",
+            BlindsAction::SetPosition { .. } => "set_position",
+            BlindsAction::SetTilt { .. } => "set_tilt",
+            BlindsAction::Calibrate => "calibrate",
+        }
+    }
+}
+
+/// Payload of `command` topic and `POST /command`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlindsCommand {
+    pub action: BlindsAction,
+    /// Overrides quiet hours for this command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<MotionMode>,
+    /// Echoed back in command result
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Topic on which command result is published
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_topic: Option<String>,
+}
+
+impl BlindsCommand {
+    pub fn new(action: BlindsAction) -> Self {
+        Self {
+            action,
+            mode: None,
+            correlation_id: None,
+            response_topic: None,
+        }
+    }
+}
+
+/// Payload of legacy `state` topic
+///
+/// New readers should use [`BlindsStatus`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateUpdate {
+    pub state: BlindsState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+}
+
+/// Where a command came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandSource {
+    Http,
+    Mqtt,
+    Switch,
+    /// Source added by a newer service
+    #[serde(other)]
+    Unknown,
+}
+
+impl CommandSource {
+    /// Same as serialized name
+    pub fn label(&self) -> &'static str {
+        match self {
+            CommandSource::Http => "http",
+            CommandSource::Mqtt => "mqtt",
+            CommandSource::Switch => "switch",
+            CommandSource::Unknown => "unknown",
+        }
+    }
+}
+
+/// Why a command failed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandError {
+    /// `DriverError` variant such as `SlatsNotOpen` or `InvalidPayload`/`Other`
+    pub kind: String,
+    pub message: String,
+}
+
+/// Payload of `status` topic and `GET /state`
+///
+/// Positions are fractions with 1.0 being fully open. Tilt goes from -1.0 closed to the left
+/// over 0.0 open to 1.0 closed to the right. Unknown values are left out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlindsStatus {
+    /// [`PROTOCOL_VERSION`] of the service that produced this
+    pub version: u32,
+    pub timestamp: DateTime<Utc>,
+    pub state: BlindsState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tilt: Option<f32>,
+    /// Position requested by running command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_position: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_command_source: Option<CommandSource>,
+    pub calibrated: bool,
+    /// Error of last command. Cleared when next command starts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<CommandError>,
+}
+
+/// Click of zigbee2mqtt wireless switch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SwitchAction {
+    Single,
+    Double,
+    Long,
+    /// Other switch events such as releasing a long press
+    #[serde(other)]
+    Unknown,
+}
+
+/// Message published by zigbee2mqtt wireless switch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SwitchPayload {
+    pub action: SwitchAction,
+    pub battery: f32,
+    pub linkquality: f32,
+    pub voltage: f32,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use std::fmt::Debug;
+
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: T) {
+        let json = serde_json::to_string(&value).unwrap();
+        let parsed: T = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, value, "{json}");
+    }
+
+    #[test]
+    fn commands_round_trip() {
+        round_trip(BlindsCommand::new(BlindsAction::Open));
+        round_trip(BlindsCommand {
+            action: BlindsAction::Partial { open: 0.25 },
+            mode: Some(MotionMode::Quiet),
+            correlation_id: Some("abc".to_owned()),
+            response_topic: Some("client/response".to_owned()),
+        });
+        round_trip(BlindsCommand::new(BlindsAction::Preset {
+            name: "evening".to_owned(),
+        }));
+        round_trip(BlindsCommand::new(BlindsAction::SetTilt { tilt: -0.5 }));
+    }
+
+    #[test]
+    fn state_round_trip() {
+        for state in BlindsState::ALL {
+            round_trip(StateUpdate {
+                state,
+                preset: None,
+            });
+            assert_eq!(
+                serde_json::to_string(&state).unwrap(),
+                format!("\"{}\"", state.label())
+            );
+        }
+        round_trip(StateUpdate {
+            state: BlindsState::Partial,
+            preset: Some("evening".to_owned()),
+        });
+    }
+
+    #[test]
+    fn status_round_trip() {
+        round_trip(BlindsStatus {
+            version: PROTOCOL_VERSION,
+            timestamp: "2024-05-01T03:02:11.120Z".parse().unwrap(),
+            state: BlindsState::Opening,
+            preset: None,
+            position: None,
+            tilt: Some(-0.5),
+            target_position: Some(1.0),
+            last_command_source: Some(CommandSource::Switch),
+            calibrated: true,
+            last_error: Some(CommandError {
+                kind: "WaitingForStopTimedOut".to_owned(),
+                message: "waiting for stop timed out".to_owned(),
+            }),
+        });
+        let status: BlindsStatus = serde_json::from_str(
+            r#"{"version": 1, "timestamp": "2024-05-01T03:02:11Z", "state": "closed", "position": 0.0, "calibrated": false}"#,
+        )
+        .unwrap();
+        assert_eq!(status.position, Some(0.0));
+        assert_eq!(status.last_command_source, None);
+    }
+
+    #[test]
+    fn switch_payload_round_trip() {
+        round_trip(SwitchPayload {
+            action: SwitchAction::Double,
+            battery: 91.0,
+            linkquality: 120.0,
+            voltage: 3.0,
+        });
+    }
+
+    /// Payloads documented in README must keep parsing
+    #[test]
+    fn parses_documented_payloads() {
+        let command: BlindsCommand = serde_json::from_str(
+            r#"{"action": {"partial": {"open": 0.3}}, "mode": "quiet", "correlation_id": "1"}"#,
+        )
+        .unwrap();
+        assert_eq!(command.action, BlindsAction::Partial { open: 0.3 });
+        assert_eq!(command.mode, Some(MotionMode::Quiet));
+        let command: BlindsCommand = serde_json::from_str(r#"{"action": "toggle"}"#).unwrap();
+        assert_eq!(command, BlindsCommand::new(BlindsAction::Toggle));
+        let update: StateUpdate =
+            serde_json::from_str(r#"{"state": "open", "unknown_field": 1}"#).unwrap();
+        assert_eq!(update.state, BlindsState::Open);
+        let switch: SwitchPayload = serde_json::from_str(
+            r#"{"action": "long", "battery": 100, "linkquality": 65, "voltage": 3.1}"#,
+        )
+        .unwrap();
+        assert_eq!(switch.action, SwitchAction::Long);
+    }
+
+    #[test]
+    fn unknown_variants_parse() {
+        let update: StateUpdate = serde_json::from_str(r#"{"state": "jammed"}"#).unwrap();
+        assert_eq!(update.state, BlindsState::Unknown);
+        let source: CommandSource = serde_json::from_str(r#""scheduler""#).unwrap();
+        assert_eq!(source, CommandSource::Unknown);
+        let switch: SwitchPayload = serde_json::from_str(
+            r#"{"action": "release", "battery": 100, "linkquality": 65, "voltage": 3.1}"#,
+        )
+        .unwrap();
+        assert_eq!(switch.action, SwitchAction::Unknown);
+        // commands are only run when the service understands them
+        assert!(serde_json::from_str::<BlindsCommand>(r#"{"action": "dance"}"#).is_err());
+        assert!(
+            serde_json::from_str::<BlindsCommand>(r#"{"action": "open", "mode": "turbo"}"#)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use crate::config::{AuthConfig, TokenScope};
+use actix_web::{
+    dev::Payload, error::InternalError, http::header, web, FromRequest, HttpRequest, HttpResponse,
+};
+use log::*;
+use std::future::{ready, Ready};
+
+/// Extractor that requires a token with read scope when authentication is enabled
+pub struct ReadAccess;
+
+impl FromRequest for ReadAccess {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(authorize(req, TokenScope::Read).map(|_| ReadAccess))
+    }
+}
+
+/// Extractor that requires a token with control scope when authentication is enabled
+pub struct ControlAccess;
+
+impl FromRequest for ControlAccess {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(authorize(req, TokenScope::Control).map(|_| ControlAccess))
+    }
+}
+
+fn authorize(req: &HttpRequest, required_scope: TokenScope) -> Result<(), actix_web::Error> {
+    let auth_config = match req.app_data::<web::Data<AuthConfig>>() {
+        Some(auth_config) => auth_config,
+        // authentication is disabled
+        None => return Ok(()),
+    };
+
+    let peer = req
+        .peer_addr()
+        .map(|address| address.to_string())
+        .unwrap_or_else(|| String::from("unknown"));
+
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let token = match token {
+        Some(token) => token,
+        None => {
+            warn!(
+                "Audit: rejected {} {} from {} without bearer token",
+                req.method(),
+                req.path(),
+                peer
+            );
+            return Err(unauthorized("missing bearer token"));
+        }
+    };
+
+    let api_token = match auth_config
+        .tokens
+        .iter()
+        .find(|api_token| constant_time_eq(api_token.token.as_bytes(), token.as_bytes()))
+    {
+        Some(api_token) => api_token,
+        None => {
+            warn!(
+                "Audit: rejected {} {} from {} with unknown token",
+                req.method(),
+                req.path(),
+                peer
+            );
+            return Err(unauthorized("invalid bearer token"));
+        }
+    };
+
+    if !api_token.scope.allows(required_scope) {
+        warn!(
+            "Audit: forbidden {} {} from {} for token {} with scope {:?}",
+            req.method(),
+            req.path(),
+            peer,
+            api_token.name,
+            api_token.scope
+        );
+        return Err(InternalError::from_response(
+            "insufficient token scope",
+            HttpResponse::Forbidden().finish(),
+        )
+        .into());
+    }
+
+    info!(
+        "Audit: allowed {} {} from {} for token {}",
+        req.method(),
+        req.path(),
+        peer,
+        api_token.name
+    );
+    Ok(())
+}
+
+fn unauthorized(reason: &'static str) -> actix_web::Error {
+    InternalError::from_response(
+        reason,
+        HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+            .finish(),
+    )
+    .into()
+}
+
+/// Compare tokens without leaking the position of the first mismatch through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::ApiToken;
+    use actix_web::{get, http::StatusCode, post, test, App, Responder};
+
+    #[get("/state")]
+    async fn read_handler(_access: ReadAccess) -> impl Responder {
+        HttpResponse::Ok().finish()
+    }
+
+    #[post("/open")]
+    async fn control_handler(_access: ControlAccess) -> impl Responder {
+        HttpResponse::Ok().finish()
+    }
+
+    fn auth_config() -> AuthConfig {
+        AuthConfig {
+            tokens: vec![
+                ApiToken {
+                    name: "dashboard".to_owned(),
+                    token: "read-token".to_owned(),
+                    scope: TokenScope::Read,
+                },
+                ApiToken {
+                    name: "home-assistant".to_owned(),
+                    token: "control-token".to_owned(),
+                    scope: TokenScope::Control,
+                },
+            ],
+        }
+    }
+
+    fn with_token(request: test::TestRequest, token: Option<&str>) -> test::TestRequest {
+        match token {
+            Some(token) => {
+                request.insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            }
+            None => request,
+        }
+    }
+
+    #[actix_web::test]
+    async fn everything_allowed_without_auth_config() {
+        let app =
+            test::init_service(App::new().service(read_handler).service(control_handler)).await;
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri("/state").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response =
+            test::call_service(&app, test::TestRequest::post().uri("/open").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn missing_or_invalid_token_is_unauthorized() {
+        let app = test::init_service(
+            App::new()
+                .service(read_handler)
+                .service(control_handler)
+                .app_data(web::Data::new(auth_config())),
+        )
+        .await;
+        for token in [None, Some("wrong-token"), Some("read-token ")] {
+            let response = test::call_service(
+                &app,
+                with_token(test::TestRequest::get().uri("/state"), token).to_request(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{token:?}");
+            assert_eq!(
+                response.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+                "Bearer"
+            );
+        }
+        // token without bearer scheme
+        let request = test::TestRequest::post()
+            .uri("/open")
+            .insert_header((header::AUTHORIZATION, "control-token"));
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn scopes_limit_access() {
+        let app = test::init_service(
+            App::new()
+                .service(read_handler)
+                .service(control_handler)
+                .app_data(web::Data::new(auth_config())),
+        )
+        .await;
+        let response = test::call_service(
+            &app,
+            with_token(test::TestRequest::get().uri("/state"), Some("read-token")).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(
+            &app,
+            with_token(test::TestRequest::post().uri("/open"), Some("read-token")).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        for request in [
+            test::TestRequest::get().uri("/state"),
+            test::TestRequest::post().uri("/open"),
+        ] {
+            let response = test::call_service(
+                &app,
+                with_token(request, Some("control-token")).to_request(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+}
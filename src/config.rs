@@ -1,19 +1,40 @@
 use crate::{
-    driver::{BedroomBlinds, Blinds, LivingRoomBlinds},
+    driver::{find_usb_serial_port, BedroomBlinds, Blinds, LivingRoomBlinds},
     error::DriverError,
 };
 use anyhow::Result;
+use blinds_protocol::BlindsAction;
+pub use blinds_protocol::MotionMode;
+use chrono::NaiveTime;
 use directories::ProjectDirs;
-use log::*;
+use log::{info, LevelFilter};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct BlindsConfig {
     living_room_blinds: Option<LivingRoomBlindsConfig>,
     bedroom_blinds: Option<BedroomBlindsConfig>,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    /// Level of modules not listed in `log_modules`
+    #[serde(default = "default_log_level")]
+    #[schemars(with = "String")]
+    pub log_level: LevelFilter,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Levels of single modules such as `blinds::driver`
+    #[serde(default)]
+    #[schemars(with = "BTreeMap<String, String>")]
+    pub log_modules: BTreeMap<String, LevelFilter>,
 }
 
 impl Default for BlindsConfig {
@@ -24,78 +45,483 @@ impl Default for BlindsConfig {
         Self {
             living_room_blinds: Some(living_room_blinds_config),
             bedroom_blinds: None,
+            http: HttpConfig::default(),
+            history: HistoryConfig::default(),
+            log_level: default_log_level(),
+            log_format: LogFormat::default(),
+            log_modules: BTreeMap::new(),
+        }
+    }
+}
+
+const fn default_log_level() -> LevelFilter {
+    LevelFilter::Info
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Plain text lines on stdout
+    #[default]
+    Human,
+    /// JSON object per line on stdout
+    Json,
+    /// Native journald protocol with structured fields
+    Journald,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HttpConfig {
+    #[serde(default = "default_http_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_http_bind_addresses")]
+    pub bind_addresses: Vec<String>,
+    #[serde(default = "default_http_port")]
+    pub port: u16,
+    pub tls: Option<HttpTlsConfig>,
+    /// Additional listener on a unix domain socket for local only control
+    pub unix_socket: Option<PathBuf>,
+    /// Require bearer tokens for all requests except health checks when set
+    pub auth: Option<AuthConfig>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            enabled: default_http_enabled(),
+            bind_addresses: default_http_bind_addresses(),
+            port: DEFAULT_HTTP_PORT,
+            tls: None,
+            unix_socket: None,
+            auth: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HttpTlsConfig {
+    /// PEM encoded certificate chain
+    pub cert_file: PathBuf,
+    /// PEM encoded private key
+    pub key_file: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AuthConfig {
+    pub tokens: Vec<ApiToken>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ApiToken {
+    /// Name used in audit log
+    pub name: String,
+    pub token: String,
+    pub scope: TokenScope,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    /// Query state
+    Read,
+    /// Query state, move blinds and run calibration
+    Control,
+}
+
+impl TokenScope {
+    pub fn allows(&self, required: TokenScope) -> bool {
+        match self {
+            TokenScope::Control => true,
+            TokenScope::Read => required == TokenScope::Read,
+        }
+    }
+}
+
+const fn default_http_enabled() -> bool {
+    true
+}
+
+const DEFAULT_HTTP_PORT: u16 = 8080;
+
+const fn default_http_port() -> u16 {
+    DEFAULT_HTTP_PORT
+}
+
+fn default_http_bind_addresses() -> Vec<String> {
+    vec![String::from("0.0.0.0")]
+}
+
+/// Log of finished motions
+///
+/// Read only on start
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Defaults to `history.jsonl` in `$STATE_DIRECTORY` or next to the config file
+    pub file: Option<PathBuf>,
+    /// Number of motions kept
+    pub capacity: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            file: None,
+            capacity: 1000,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl HistoryConfig {
+    pub fn file_path(&self, config_path: &Path) -> PathBuf {
+        if let Some(ref file) = self.file {
+            return file.clone();
+        }
+        // set by systemd for StateDirectory
+        let directory = std::env::var_os("STATE_DIRECTORY")
+            .map(PathBuf::from)
+            .or_else(|| config_path.parent().map(Path::to_owned))
+            .unwrap_or_default();
+        directory.join("history.jsonl")
+    }
+}
+
+/// Values from environment variables and command line that take precedence over config file
+#[derive(Debug, Default)]
+pub struct ConfigOverrides {
+    pub broker_host: Option<String>,
+    pub broker_port: Option<u16>,
+    pub mqtt_username: Option<String>,
+    pub mqtt_password: Option<String>,
+    pub serial_port: Option<String>,
+    pub http_bind_addresses: Vec<String>,
+    pub http_port: Option<u16>,
+    pub log_level: Option<LevelFilter>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct BedroomBlindsConfig {
-    pub serial_port: String,
+    pub serial_port: SerialPortConfig,
     pub motor_id: u8,
     pub top_position: Option<f32>,
+    #[serde(default)]
+    pub lift_profile: MotionProfile,
+    #[serde(default)]
+    pub quiet_mode: QuietModeConfig,
     pub mqtt: MqttConfig,
+    #[serde(default)]
+    pub presets: BTreeMap<String, BedroomPreset>,
+}
+
+/// Serial port path such as `/dev/ttyUSB0` or USB adapter to look for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum SerialPortConfig {
+    Path(String),
+    Usb(UsbSerialMatcher),
+}
+
+impl Default for SerialPortConfig {
+    fn default() -> Self {
+        SerialPortConfig::Path(String::from("/dev/ttyUSB0"))
+    }
+}
+
+impl SerialPortConfig {
+    /// Current tty of the port
+    pub fn resolve(&self) -> Result<PathBuf> {
+        match self {
+            SerialPortConfig::Path(path) => Ok(PathBuf::from(path)),
+            SerialPortConfig::Usb(matcher) => find_usb_serial_port(matcher),
+        }
+    }
+}
+
+impl std::fmt::Display for SerialPortConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SerialPortConfig::Path(path) => write!(f, "{path}"),
+            SerialPortConfig::Usb(matcher) => write!(f, "{matcher}"),
+        }
+    }
+}
+
+/// All set fields have to match. IDs are hex as shown by `lsusb`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UsbSerialMatcher {
+    pub vendor_id: Option<String>,
+    pub product_id: Option<String>,
+    /// Needed when more adapters of the same type are connected
+    pub serial_number: Option<String>,
+}
+
+impl UsbSerialMatcher {
+    pub fn is_empty(&self) -> bool {
+        self.vendor_id.is_none() && self.product_id.is_none() && self.serial_number.is_none()
+    }
+
+    pub fn matches(
+        &self,
+        vendor_id: Option<&str>,
+        product_id: Option<&str>,
+        serial_number: Option<&str>,
+    ) -> bool {
+        fn field_matches(expected: &Option<String>, actual: Option<&str>) -> bool {
+            match (expected, actual) {
+                (None, _) => true,
+                (Some(expected), Some(actual)) => expected.eq_ignore_ascii_case(actual),
+                (Some(_), None) => false,
+            }
+        }
+        field_matches(&self.vendor_id, vendor_id)
+            && field_matches(&self.product_id, product_id)
+            && field_matches(&self.serial_number, serial_number)
+    }
+}
+
+impl std::fmt::Display for UsbSerialMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "USB {}:{}",
+            self.vendor_id.as_deref().unwrap_or("*"),
+            self.product_id.as_deref().unwrap_or("*")
+        )?;
+        if let Some(ref serial_number) = self.serial_number {
+            write!(f, " serial {serial_number}")?;
+        }
+        Ok(())
+    }
+}
+
+/// How motor speed changes at the start and end of a move
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MotionProfile {
+    /// Full speed right away
+    #[default]
+    Constant,
+    /// Linear speed ramps with acceleration in degrees per second squared
+    Trapezoidal { acceleration: f32 },
+    /// Smooth speed ramps with peak acceleration in degrees per second squared
+    SCurve { acceleration: f32 },
+}
+
+impl MotionProfile {
+    /// Ramps divide by acceleration so it has to be a positive number
+    pub fn is_valid_acceleration(acceleration: f32) -> bool {
+        acceleration.is_finite() && acceleration > 0.0
+    }
+
+    pub fn validate(&self, field: &str) -> Result<()> {
+        match *self {
+            MotionProfile::Constant => Ok(()),
+            MotionProfile::Trapezoidal { acceleration }
+            | MotionProfile::SCurve { acceleration } => {
+                if !Self::is_valid_acceleration(acceleration) {
+                    anyhow::bail!(
+                        "{field}.acceleration {acceleration} has to be a positive number"
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Unset fields keep their defaults
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct QuietModeConfig {
+    /// Fraction of normal speed
+    pub speed_factor: f32,
+    /// Fraction of normal current limits
+    pub current_factor: f32,
+    /// Local time range in which quiet mode is used unless command selects a mode
+    pub hours: Option<QuietHours>,
+}
+
+impl Default for QuietModeConfig {
+    fn default() -> Self {
+        QuietModeConfig {
+            speed_factor: 0.5,
+            current_factor: 0.75,
+            hours: None,
+        }
+    }
+}
+
+impl QuietModeConfig {
+    /// Factors divide motion timeouts so they can't be zero or negative
+    pub fn is_valid_factor(factor: f32) -> bool {
+        factor > 0.0 && factor <= 1.0
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for (field, factor) in [
+            ("speed_factor", self.speed_factor),
+            ("current_factor", self.current_factor),
+        ] {
+            if !Self::is_valid_factor(factor) {
+                anyhow::bail!(
+                    "quiet_mode.{field} {factor} has to be more than 0.0 and at most 1.0"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Mode used when command doesn't select one
+    pub fn mode_at(&self, time: NaiveTime) -> MotionMode {
+        match self.hours {
+            Some(ref hours) if hours.contains(time) => MotionMode::Quiet,
+            _ => MotionMode::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct QuietHours {
+    /// Such as `22:00`
+    pub start: NaiveTime,
+    /// Such as `07:30`. Can be before start to span midnight
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    /// Start is included and end isn't. Equal start and end is never quiet
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// Named position of bedroom blinds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BedroomPreset {
+    /// Between 0.0 and 1.0 with 1.0 being fully open
+    pub height: f32,
 }
 
 impl Default for BedroomBlindsConfig {
     fn default() -> Self {
         BedroomBlindsConfig {
-            serial_port: String::from("/dev/ttyUSB0"),
+            serial_port: SerialPortConfig::default(),
             motor_id: 1,
             top_position: None,
+            lift_profile: MotionProfile::default(),
+            quiet_mode: QuietModeConfig::default(),
             mqtt: MqttConfig::default(),
+            presets: BTreeMap::new(),
         }
     }
 }
 
 impl BedroomBlindsConfig {
-    #[allow(dead_code)]
-    // TODO(David): implement bedroom blinds
-    pub async fn save(&self, path: &Path) -> Result<()> {
-        let config = BlindsConfig {
-            bedroom_blinds: Some(self.clone()),
-            living_room_blinds: None,
-        };
-        let contents = serde_yaml::to_vec(&config)?;
-        let mut file = File::create(path).await?;
-        file.write_all(&contents).await?;
-        Ok(())
+    /// Write calibration values to config file
+    ///
+    /// Everything else in the file is kept as is so that overrides don't get persisted
+    pub async fn save_calibration(&self, path: &Path) -> Result<()> {
+        let mut config = BlindsConfig::load_or_empty(path).await?;
+        let bedroom_blinds = config.bedroom_blinds.get_or_insert_with(|| self.clone());
+        bedroom_blinds.top_position = self.top_position;
+        config.save(path).await
+    }
+
+    /// Name of preset matching given height
+    pub fn preset_at(&self, height: f32) -> Option<&str> {
+        self.presets
+            .iter()
+            .find(|(_, preset)| fractions_match(preset.height, height))
+            .map(|(name, _)| name.as_str())
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct LivingRoomBlindsConfig {
-    pub serial_port: String,
+    pub serial_port: SerialPortConfig,
     pub slide_motor_id: u8,
     pub flip_motor_id: u8,
     pub flip_motor_left: Option<f32>,
     pub flip_motor_right: Option<f32>,
+    /// Slide motor position with curtain against closed end stop
+    pub slide_closed_position: Option<f32>,
+    /// Slide motor position with curtain against open end stop
+    pub slide_open_position: Option<f32>,
+    #[serde(default)]
+    pub slide_profile: MotionProfile,
+    #[serde(default)]
+    pub quiet_mode: QuietModeConfig,
     pub mqtt: MqttConfig,
+    #[serde(default)]
+    pub presets: BTreeMap<String, LivingRoomPreset>,
+}
+
+/// Named position of living room blinds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct LivingRoomPreset {
+    /// Between 0.0 and 1.0 with 1.0 being fully open
+    pub slide: f32,
+    /// Between 0.0 and 1.0 with 1.0 being slats fully open
+    ///
+    /// Only used when curtain is closed
+    pub tilt: f32,
 }
 
 impl Default for LivingRoomBlindsConfig {
     fn default() -> Self {
         LivingRoomBlindsConfig {
-            serial_port: String::from("/dev/ttyUSB0"),
+            serial_port: SerialPortConfig::default(),
             slide_motor_id: 1,
             flip_motor_id: 2,
             flip_motor_left: None,
             flip_motor_right: None,
+            slide_closed_position: None,
+            slide_open_position: None,
+            slide_profile: MotionProfile::default(),
+            quiet_mode: QuietModeConfig::default(),
             mqtt: MqttConfig::default(),
+            presets: BTreeMap::new(),
         }
     }
 }
 
 impl LivingRoomBlindsConfig {
-    pub async fn save(&self, path: &Path) -> Result<()> {
-        let config = BlindsConfig {
-            living_room_blinds: Some(self.clone()),
-            bedroom_blinds: None,
-        };
-        let contents = serde_yaml::to_vec(&config)?;
-        let mut file = File::create(path).await?;
-        file.write_all(&contents).await?;
-        Ok(())
+    /// Write calibration values to config file
+    ///
+    /// Everything else in the file is kept as is so that overrides don't get persisted
+    pub async fn save_calibration(&self, path: &Path) -> Result<()> {
+        let mut config = BlindsConfig::load_or_empty(path).await?;
+        let living_room_blinds = config
+            .living_room_blinds
+            .get_or_insert_with(|| self.clone());
+        living_room_blinds.flip_motor_left = self.flip_motor_left;
+        living_room_blinds.flip_motor_right = self.flip_motor_right;
+        living_room_blinds.slide_closed_position = self.slide_closed_position;
+        living_room_blinds.slide_open_position = self.slide_open_position;
+        config.save(path).await
     }
+
+    /// Name of preset matching given slide and tilt
+    pub fn preset_at(&self, slide: f32, tilt: f32) -> Option<&str> {
+        self.presets
+            .iter()
+            .find(|(_, preset)| {
+                fractions_match(preset.slide, slide)
+                    && (slide > 0.0 || fractions_match(preset.tilt, tilt))
+            })
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+const PRESET_TOLERANCE: f32 = 0.01;
+
+fn fractions_match(a: f32, b: f32) -> bool {
+    (a - b).abs() < PRESET_TOLERANCE
 }
 
 impl BlindsConfig {
@@ -103,7 +529,28 @@ impl BlindsConfig {
         let mut file = File::open(path).await?;
         let mut contents = vec![];
         file.read_to_end(&mut contents).await?;
-        Ok(serde_yaml::from_slice(&contents)?)
+        let config: Self = serde_yaml::from_slice(&contents)?;
+        if let Some(living_room_blinds) = &config.living_room_blinds {
+            living_room_blinds.quiet_mode.validate()?;
+            living_room_blinds.slide_profile.validate("slide_profile")?;
+        }
+        if let Some(bedroom_blinds) = &config.bedroom_blinds {
+            bedroom_blinds.quiet_mode.validate()?;
+            bedroom_blinds.lift_profile.validate("lift_profile")?;
+        }
+        Ok(config)
+    }
+
+    async fn load_or_empty(path: &Path) -> Result<Self> {
+        if path.exists() {
+            Self::load(path).await
+        } else {
+            Ok(Self {
+                living_room_blinds: None,
+                bedroom_blinds: None,
+                ..Default::default()
+            })
+        }
     }
 
     pub async fn save(&self, path: &Path) -> Result<()> {
@@ -113,24 +560,94 @@ impl BlindsConfig {
         Ok(())
     }
 
+    /// JSON Schema of the config file for editor autocompletion
+    pub fn json_schema() -> schemars::schema::RootSchema {
+        schemars::schema_for!(BlindsConfig)
+    }
+
     pub fn default_config_location() -> Option<PathBuf> {
         ProjectDirs::from("com", "dmw", "blinds_app").map(|dirs| dirs.config_dir().to_owned())
     }
 
-    pub async fn driver_from_config(self) -> Result<(Box<dyn Blinds>, MqttConfig)> {
+    /// MQTT config of the configured room
+    pub fn mqtt_config(&self) -> Result<MqttConfig> {
+        match (&self.living_room_blinds, &self.bedroom_blinds) {
+            (Some(living_room_blinds), None) => Ok(living_room_blinds.mqtt.clone()),
+            (None, Some(bedroom_blinds)) => Ok(bedroom_blinds.mqtt.clone()),
+            (None, None) => Err(DriverError::MissingRoomConfiguration.into()),
+            (_, _) => Err(DriverError::BothRoomConfigsPresent.into()),
+        }
+    }
+
+    pub fn apply_overrides(&mut self, overrides: &ConfigOverrides) {
+        let (serial_port, mqtt) = match (&mut self.living_room_blinds, &mut self.bedroom_blinds) {
+            (Some(living_room_blinds), _) => (
+                Some(&mut living_room_blinds.serial_port),
+                Some(&mut living_room_blinds.mqtt),
+            ),
+            (None, Some(bedroom_blinds)) => (
+                Some(&mut bedroom_blinds.serial_port),
+                Some(&mut bedroom_blinds.mqtt),
+            ),
+            (None, None) => (None, None),
+        };
+        if let (Some(serial_port), Some(new_serial_port)) = (serial_port, &overrides.serial_port) {
+            *serial_port = SerialPortConfig::Path(new_serial_port.clone());
+        }
+        if let Some(mqtt) = mqtt {
+            if let Some(broker_host) = &overrides.broker_host {
+                mqtt.broker_host = broker_host.clone();
+            }
+            if let Some(broker_port) = overrides.broker_port {
+                mqtt.broker_port = broker_port;
+            }
+            if let Some(username) = &overrides.mqtt_username {
+                mqtt.username = Some(username.clone());
+            }
+            if let Some(password) = &overrides.mqtt_password {
+                mqtt.password = Some(password.clone());
+            }
+        }
+        if !overrides.http_bind_addresses.is_empty() {
+            self.http.bind_addresses = overrides.http_bind_addresses.clone();
+        }
+        if let Some(http_port) = overrides.http_port {
+            self.http.port = http_port;
+        }
+        if let Some(log_level) = overrides.log_level {
+            self.log_level = log_level;
+        }
+    }
+
+    pub fn living_room_blinds(&self) -> Option<&LivingRoomBlindsConfig> {
+        self.living_room_blinds.as_ref()
+    }
+
+    pub fn bedroom_blinds(&self) -> Option<&BedroomBlindsConfig> {
+        self.bedroom_blinds.as_ref()
+    }
+
+    /// Calibration is saved to `config_path`
+    pub async fn driver_from_config(
+        self,
+        config_path: &Path,
+    ) -> Result<(Box<dyn Blinds>, MqttConfig)> {
         match (self.living_room_blinds, self.bedroom_blinds) {
             (Some(living_room_blinds), None) => {
                 info!("Loading living room blinds mode");
                 let mqtt = living_room_blinds.mqtt.clone();
                 Ok((
-                    Box::new(LivingRoomBlinds::new(living_room_blinds).await?),
+                    Box::new(LivingRoomBlinds::new(living_room_blinds, config_path).await?),
                     mqtt,
                 ))
             }
             (None, Some(bedroom_blinds)) => {
                 info!("Loading bedroom blinds mode");
                 let mqtt = bedroom_blinds.mqtt.clone();
-                Ok((Box::new(BedroomBlinds::new(bedroom_blinds).await?), mqtt))
+                Ok((
+                    Box::new(BedroomBlinds::new(bedroom_blinds, config_path).await?),
+                    mqtt,
+                ))
             }
             (None, None) => Err(DriverError::MissingRoomConfiguration.into()),
             (_, _) => Err(DriverError::BothRoomConfigsPresent.into()),
@@ -151,14 +668,67 @@ const fn default_mqtt_port() -> u16 {
     DEFAULT_MQTT_PORT
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, JsonSchema)]
 pub struct MqttConfig {
     pub base_route: String,
     pub broker_host: String,
     #[serde(default = "default_mqtt_port")]
     pub broker_port: u16,
+    #[serde(default)]
+    pub protocol: MqttProtocol,
     pub client_id: String,
     pub switch_topic: Option<String>,
+    pub username: Option<String>,
+    /// Prefer `password_file` or `BLINDS_MQTT_PASSWORD` over storing password in config
+    pub password: Option<String>,
+    pub password_file: Option<PathBuf>,
+    pub tls: Option<MqttTlsConfig>,
+    /// Command results are only published to response topics starting with this prefix
+    ///
+    /// Topics under `base_route` are always rejected
+    pub response_topic_prefix: Option<String>,
+    /// Actions triggered by clicks on switch
+    #[serde(default)]
+    pub switch_bindings: SwitchBindings,
+}
+
+/// Unset fields keep their defaults. Set to `null` to ignore a click
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(default)]
+pub struct SwitchBindings {
+    pub single: Option<BlindsAction>,
+    pub double: Option<BlindsAction>,
+    pub long: Option<BlindsAction>,
+}
+
+impl Default for SwitchBindings {
+    fn default() -> Self {
+        SwitchBindings {
+            single: Some(BlindsAction::Close),
+            double: None,
+            long: Some(BlindsAction::Open),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct MqttTlsConfig {
+    /// PEM encoded CA bundle used to verify the broker
+    pub ca_file: PathBuf,
+    /// PEM encoded client certificate for mutual TLS
+    pub client_cert_file: Option<PathBuf>,
+    /// PEM encoded client key for mutual TLS
+    pub client_key_file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MqttProtocol {
+    /// MQTT v3.1.1
+    #[default]
+    V3,
+    /// MQTT v5 with message expiry, user properties and request/response topics
+    V5,
 }
 
 impl Default for MqttConfig {
@@ -167,8 +737,190 @@ impl Default for MqttConfig {
             base_route: "living_room/blinds".to_owned(),
             broker_host: "mqtt".to_owned(),
             broker_port: DEFAULT_MQTT_PORT,
+            protocol: MqttProtocol::default(),
             client_id: "living_room_blinds".to_owned(),
             switch_topic: None,
+            username: None,
+            password: None,
+            password_file: None,
+            tls: None,
+            response_topic_prefix: None,
+            switch_bindings: SwitchBindings::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LIVING_ROOM_CONFIG: &str = "\
+living_room_blinds:
+  serial_port: /dev/ttyUSB0
+  slide_motor_id: 1
+  flip_motor_id: 2
+  mqtt:
+    base_route: living_room/blinds
+    broker_host: file-broker
+    broker_port: 1883
+    client_id: living_room_blinds
+    username: file-user
+http:
+  bind_addresses: [0.0.0.0]
+  port: 8080
+log_level: warn
+";
+
+    fn temp_config_file(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("blinds_config_{name}_{}.yaml", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn overrides_take_precedence_over_file() {
+        let mut config: BlindsConfig = serde_yaml::from_str(LIVING_ROOM_CONFIG).unwrap();
+        config.apply_overrides(&ConfigOverrides {
+            broker_host: Some("override-broker".to_owned()),
+            broker_port: Some(8883),
+            mqtt_password: Some("secret".to_owned()),
+            serial_port: Some("/dev/ttyUSB1".to_owned()),
+            http_bind_addresses: vec!["127.0.0.1".to_owned(), "::1".to_owned()],
+            http_port: Some(8081),
+            log_level: Some(LevelFilter::Debug),
+            ..Default::default()
+        });
+        let living_room = config.living_room_blinds().unwrap();
+        assert_eq!(
+            living_room.serial_port,
+            SerialPortConfig::Path("/dev/ttyUSB1".to_owned())
+        );
+        assert_eq!(living_room.mqtt.broker_host, "override-broker");
+        assert_eq!(living_room.mqtt.broker_port, 8883);
+        assert_eq!(living_room.mqtt.password.as_deref(), Some("secret"));
+        // not overridden
+        assert_eq!(living_room.mqtt.username.as_deref(), Some("file-user"));
+        assert_eq!(config.http.bind_addresses, vec!["127.0.0.1", "::1"]);
+        assert_eq!(config.http.port, 8081);
+        assert_eq!(config.log_level, LevelFilter::Debug);
+    }
+
+    #[test]
+    fn empty_overrides_keep_file() {
+        let mut config: BlindsConfig = serde_yaml::from_str(LIVING_ROOM_CONFIG).unwrap();
+        config.apply_overrides(&ConfigOverrides::default());
+        let living_room = config.living_room_blinds().unwrap();
+        assert_eq!(living_room.mqtt.broker_host, "file-broker");
+        assert_eq!(living_room.mqtt.broker_port, 1883);
+        assert_eq!(config.http.bind_addresses, vec!["0.0.0.0"]);
+        assert_eq!(config.http.port, 8080);
+        assert_eq!(config.log_level, LevelFilter::Warn);
+    }
+
+    #[tokio::test]
+    async fn calibration_does_not_save_overrides() {
+        let path = temp_config_file("calibration");
+        std::fs::write(&path, LIVING_ROOM_CONFIG).unwrap();
+        let mut config = BlindsConfig::load(&path).await.unwrap();
+        config.apply_overrides(&ConfigOverrides {
+            broker_host: Some("override-broker".to_owned()),
+            mqtt_password: Some("secret".to_owned()),
+            serial_port: Some("/dev/ttyUSB1".to_owned()),
+            http_port: Some(8081),
+            ..Default::default()
+        });
+        let mut living_room = config.living_room_blinds().unwrap().clone();
+        living_room.flip_motor_left = Some(-90.0);
+        living_room.flip_motor_right = Some(90.0);
+        living_room.slide_closed_position = Some(0.0);
+        living_room.slide_open_position = Some(-3600.0);
+        living_room.save_calibration(&path).await.unwrap();
+
+        let saved = BlindsConfig::load(&path).await.unwrap();
+        let saved_living_room = saved.living_room_blinds().unwrap();
+        assert_eq!(saved_living_room.flip_motor_left, Some(-90.0));
+        assert_eq!(saved_living_room.slide_open_position, Some(-3600.0));
+        assert_eq!(
+            saved_living_room.serial_port,
+            SerialPortConfig::Path("/dev/ttyUSB0".to_owned())
+        );
+        assert_eq!(saved_living_room.mqtt.broker_host, "file-broker");
+        assert_eq!(saved_living_room.mqtt.password, None);
+        assert_eq!(saved.http.port, 8080);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn load_rejects_non_positive_acceleration() {
+        let path = temp_config_file("acceleration");
+        let contents = LIVING_ROOM_CONFIG.replace(
+            "  flip_motor_id: 2\n",
+            "  flip_motor_id: 2\n  slide_profile:\n    type: trapezoidal\n    acceleration: 0.0\n",
+        );
+        std::fs::write(&path, contents).unwrap();
+        let error = BlindsConfig::load(&path).await.unwrap_err();
+        assert!(error.to_string().contains("slide_profile.acceleration"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn quiet_hours_within_day() {
+        let hours = QuietHours {
+            start: time(13, 0),
+            end: time(15, 30),
+        };
+        assert!(!hours.contains(time(12, 59)));
+        assert!(hours.contains(time(13, 0)));
+        assert!(hours.contains(time(15, 29)));
+        assert!(!hours.contains(time(15, 30)));
+        assert!(!hours.contains(time(0, 0)));
+    }
+
+    #[test]
+    fn quiet_hours_wrap_past_midnight() {
+        let hours = QuietHours {
+            start: time(22, 0),
+            end: time(7, 30),
+        };
+        assert!(!hours.contains(time(21, 59)));
+        assert!(hours.contains(time(22, 0)));
+        assert!(hours.contains(time(23, 59)));
+        assert!(hours.contains(time(0, 0)));
+        assert!(hours.contains(time(7, 29)));
+        assert!(!hours.contains(time(7, 30)));
+        assert!(!hours.contains(time(12, 0)));
+    }
+
+    #[test]
+    fn quiet_hours_with_same_start_and_end_are_empty() {
+        let hours = QuietHours {
+            start: time(22, 0),
+            end: time(22, 0),
+        };
+        assert!(!hours.contains(time(21, 59)));
+        assert!(!hours.contains(time(22, 0)));
+        assert!(!hours.contains(time(0, 0)));
+    }
+
+    #[test]
+    fn quiet_mode_is_used_during_quiet_hours() {
+        let quiet_mode = QuietModeConfig {
+            hours: Some(QuietHours {
+                start: time(22, 0),
+                end: time(7, 30),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(quiet_mode.mode_at(time(23, 0)), MotionMode::Quiet);
+        assert_eq!(quiet_mode.mode_at(time(8, 0)), MotionMode::Normal);
+        assert_eq!(
+            QuietModeConfig::default().mode_at(time(23, 0)),
+            MotionMode::Normal
+        );
+    }
+}
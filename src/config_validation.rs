@@ -0,0 +1,565 @@
+use crate::config::{
+    BlindsConfig, ConfigOverrides, MotionProfile, MqttConfig, QuietModeConfig, SerialPortConfig,
+};
+use anyhow::Result;
+use std::{fmt, net::IpAddr, path::Path};
+use tokio::{fs::File, io::AsyncReadExt};
+
+const LSS_BROADCAST_ID: u8 = 254;
+
+#[derive(Debug)]
+pub struct ConfigProblem {
+    pub path: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "<root>"
+        } else {
+            &self.path
+        };
+        match self.line {
+            Some(line) => write!(f, "{} (line {}): {}", path, line, self.message),
+            None => write!(f, "{}: {}", path, self.message),
+        }
+    }
+}
+
+/// Load config file and report every problem found in it
+///
+/// Overrides are applied first so that the config the service would run with is checked
+pub async fn check_config_file(
+    path: &Path,
+    overrides: &ConfigOverrides,
+) -> Result<Vec<ConfigProblem>> {
+    let mut file = File::open(path).await?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).await?;
+    Ok(check_config(&contents, overrides))
+}
+
+fn check_config(contents: &str, overrides: &ConfigOverrides) -> Vec<ConfigProblem> {
+    let mut config: BlindsConfig = match serde_yaml::from_str(contents) {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![ConfigProblem {
+                path: String::new(),
+                line: e.location().map(|location| location.line()),
+                message: e.to_string(),
+            }]
+        }
+    };
+    config.apply_overrides(overrides);
+
+    let mut problems = vec![];
+    let mut report = |path: &str, message: String| {
+        problems.push(ConfigProblem {
+            path: path.to_owned(),
+            line: find_key_line(contents, path),
+            message,
+        })
+    };
+
+    match (config.living_room_blinds(), config.bedroom_blinds()) {
+        (None, None) => report(
+            "",
+            "one of living_room_blinds or bedroom_blinds has to be configured".to_owned(),
+        ),
+        (Some(_), Some(_)) => report(
+            "bedroom_blinds",
+            "living_room_blinds and bedroom_blinds are both configured".to_owned(),
+        ),
+        _ => (),
+    }
+
+    if let Some(living_room) = config.living_room_blinds() {
+        check_serial_port(&mut report, "living_room_blinds", &living_room.serial_port);
+        check_motor_id(
+            &mut report,
+            "living_room_blinds.slide_motor_id",
+            living_room.slide_motor_id,
+        );
+        check_motor_id(
+            &mut report,
+            "living_room_blinds.flip_motor_id",
+            living_room.flip_motor_id,
+        );
+        if living_room.slide_motor_id == living_room.flip_motor_id {
+            report(
+                "living_room_blinds.flip_motor_id",
+                format!(
+                    "flip motor uses the same ID as slide motor {}",
+                    living_room.slide_motor_id
+                ),
+            );
+        }
+        if let (Some(left), Some(right)) =
+            (living_room.flip_motor_left, living_room.flip_motor_right)
+        {
+            if left >= right {
+                report(
+                    "living_room_blinds.flip_motor_left",
+                    format!(
+                        "flip_motor_left {left} has to be smaller than flip_motor_right {right}"
+                    ),
+                );
+            }
+        }
+        if let (Some(closed), Some(open)) = (
+            living_room.slide_closed_position,
+            living_room.slide_open_position,
+        ) {
+            if (open - closed).abs() < f32::EPSILON {
+                report(
+                    "living_room_blinds.slide_open_position",
+                    format!(
+                        "slide_open_position has to differ from slide_closed_position {closed}"
+                    ),
+                );
+            }
+        }
+        check_motion_profile(
+            &mut report,
+            "living_room_blinds.slide_profile",
+            &living_room.slide_profile,
+        );
+        check_quiet_mode(
+            &mut report,
+            "living_room_blinds.quiet_mode",
+            &living_room.quiet_mode,
+        );
+        for (name, preset) in &living_room.presets {
+            let path = format!("living_room_blinds.presets.{name}");
+            check_fraction(&mut report, &format!("{path}.slide"), preset.slide);
+            check_fraction(&mut report, &format!("{path}.tilt"), preset.tilt);
+        }
+        check_mqtt(&mut report, "living_room_blinds.mqtt", &living_room.mqtt);
+    }
+
+    if let Some(bedroom) = config.bedroom_blinds() {
+        check_serial_port(&mut report, "bedroom_blinds", &bedroom.serial_port);
+        check_motor_id(&mut report, "bedroom_blinds.motor_id", bedroom.motor_id);
+        check_motion_profile(
+            &mut report,
+            "bedroom_blinds.lift_profile",
+            &bedroom.lift_profile,
+        );
+        check_quiet_mode(
+            &mut report,
+            "bedroom_blinds.quiet_mode",
+            &bedroom.quiet_mode,
+        );
+        for (name, preset) in &bedroom.presets {
+            check_fraction(
+                &mut report,
+                &format!("bedroom_blinds.presets.{name}.height"),
+                preset.height,
+            );
+        }
+        check_mqtt(&mut report, "bedroom_blinds.mqtt", &bedroom.mqtt);
+    }
+
+    if config.history.capacity == 0 {
+        report("history.capacity", "capacity has to be positive".to_owned());
+    }
+
+    check_http_listeners(&mut report, &config);
+
+    if let Some(tls) = &config.http.tls {
+        if !tls.cert_file.exists() {
+            report(
+                "http.tls.cert_file",
+                format!(
+                    "certificate file {} does not exist",
+                    tls.cert_file.display()
+                ),
+            );
+        }
+        if !tls.key_file.exists() {
+            report(
+                "http.tls.key_file",
+                format!("key file {} does not exist", tls.key_file.display()),
+            );
+        }
+    }
+
+    problems
+}
+
+fn check_http_listeners(report: &mut impl FnMut(&str, String), config: &BlindsConfig) {
+    let http = &config.http;
+    if !http.enabled {
+        return;
+    }
+    if http.bind_addresses.is_empty() && http.unix_socket.is_none() {
+        report(
+            "http.bind_addresses",
+            "at least one bind address or unix_socket has to be set".to_owned(),
+        );
+    }
+    for address in &http.bind_addresses {
+        if !is_valid_bind_address(address) {
+            report(
+                "http.bind_addresses",
+                format!("{address:?} is not an IP address or host name"),
+            );
+        }
+    }
+    if !http.bind_addresses.is_empty() && http.port == 0 {
+        report("http.port", "port can not be 0".to_owned());
+    }
+}
+
+fn is_valid_bind_address(address: &str) -> bool {
+    address.parse::<IpAddr>().is_ok()
+        || (!address.is_empty()
+            && address
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.'))
+}
+
+fn check_serial_port(
+    report: &mut impl FnMut(&str, String),
+    room: &str,
+    serial_port: &SerialPortConfig,
+) {
+    let path = format!("{room}.serial_port");
+    match serial_port {
+        SerialPortConfig::Path(serial_port) => {
+            if !Path::new(serial_port).exists() {
+                report(&path, format!("serial port {serial_port} does not exist"));
+            }
+        }
+        SerialPortConfig::Usb(matcher) if matcher.is_empty() => report(
+            &path,
+            "one of vendor_id, product_id or serial_number has to be set".to_owned(),
+        ),
+        SerialPortConfig::Usb(_) => {
+            if let Err(e) = serial_port.resolve() {
+                report(&path, e.to_string());
+            }
+        }
+    }
+}
+
+fn check_motion_profile(
+    report: &mut impl FnMut(&str, String),
+    path: &str,
+    profile: &MotionProfile,
+) {
+    match *profile {
+        MotionProfile::Constant => (),
+        MotionProfile::Trapezoidal { acceleration } | MotionProfile::SCurve { acceleration } => {
+            if !MotionProfile::is_valid_acceleration(acceleration) {
+                report(
+                    &format!("{path}.acceleration"),
+                    format!("acceleration {acceleration} has to be a positive number"),
+                );
+            }
+        }
+    }
+}
+
+fn check_quiet_mode(
+    report: &mut impl FnMut(&str, String),
+    path: &str,
+    quiet_mode: &QuietModeConfig,
+) {
+    for (field, factor) in [
+        ("speed_factor", quiet_mode.speed_factor),
+        ("current_factor", quiet_mode.current_factor),
+    ] {
+        if !QuietModeConfig::is_valid_factor(factor) {
+            report(
+                &format!("{path}.{field}"),
+                format!("{factor} has to be more than 0.0 and at most 1.0"),
+            );
+        }
+    }
+}
+
+fn check_fraction(report: &mut impl FnMut(&str, String), path: &str, value: f32) {
+    if !(0.0..=1.0).contains(&value) {
+        report(path, format!("{value} has to be between 0.0 and 1.0"));
+    }
+}
+
+fn check_motor_id(report: &mut impl FnMut(&str, String), path: &str, id: u8) {
+    if id == LSS_BROADCAST_ID {
+        report(
+            path,
+            format!("motor ID {LSS_BROADCAST_ID} is reserved for broadcast"),
+        );
+    }
+}
+
+fn check_mqtt(report: &mut impl FnMut(&str, String), path: &str, mqtt: &MqttConfig) {
+    if mqtt.base_route.trim().is_empty() {
+        report(
+            &format!("{path}.base_route"),
+            "base_route can not be empty".to_owned(),
+        );
+    }
+    if mqtt.base_route.contains(['#', '+']) {
+        report(
+            &format!("{path}.base_route"),
+            "base_route can not contain MQTT wildcards".to_owned(),
+        );
+    }
+    if mqtt.broker_host.trim().is_empty() {
+        report(
+            &format!("{path}.broker_host"),
+            "broker_host can not be empty".to_owned(),
+        );
+    }
+    if mqtt.broker_port == 0 {
+        report(
+            &format!("{path}.broker_port"),
+            "broker_port can not be 0".to_owned(),
+        );
+    }
+    // password from config or override takes precedence over password file
+    if let (None, Some(password_file)) = (&mqtt.password, &mqtt.password_file) {
+        if !password_file.exists() {
+            report(
+                &format!("{path}.password_file"),
+                format!("password file {} does not exist", password_file.display()),
+            );
+        }
+    }
+    if let Some(tls) = &mqtt.tls {
+        let files = [
+            ("ca_file", Some(&tls.ca_file)),
+            ("client_cert_file", tls.client_cert_file.as_ref()),
+            ("client_key_file", tls.client_key_file.as_ref()),
+        ];
+        for (name, file) in files {
+            if let Some(file) = file {
+                if !file.exists() {
+                    report(
+                        &format!("{path}.tls.{name}"),
+                        format!("{} does not exist", file.display()),
+                    );
+                }
+            }
+        }
+        if tls.client_cert_file.is_some() != tls.client_key_file.is_some() {
+            report(
+                &format!("{path}.tls"),
+                "client_cert_file and client_key_file have to be set together".to_owned(),
+            );
+        }
+    }
+    if let Some(prefix) = &mqtt.response_topic_prefix {
+        if prefix.trim().is_empty() || prefix.contains(['#', '+']) {
+            report(
+                &format!("{path}.response_topic_prefix"),
+                "response_topic_prefix has to be a topic without wildcards".to_owned(),
+            );
+        } else if prefix.starts_with(&mqtt.base_route) {
+            report(
+                &format!("{path}.response_topic_prefix"),
+                "response_topic_prefix can not be under base_route".to_owned(),
+            );
+        }
+    }
+}
+
+/// Find 1 based line number of a dotted key path in YAML text
+///
+/// This only understands block mappings which is what we write when saving config
+fn find_key_line(contents: &str, path: &str) -> Option<usize> {
+    if path.is_empty() {
+        return None;
+    }
+    let mut lines = contents.lines().enumerate();
+    let mut parent_indent = None;
+    let mut found = None;
+    for key in path.split('.') {
+        let pattern = format!("{key}:");
+        loop {
+            let (number, line) = lines.next()?;
+            let trimmed = line.trim_start();
+            let indent = line.len() - trimmed.len();
+            if matches!(parent_indent, Some(parent) if indent <= parent)
+                && !trimmed.is_empty()
+                && !trimmed.starts_with('#')
+            {
+                // left the parent mapping without finding key
+                return None;
+            }
+            if trimmed.starts_with(&pattern) {
+                parent_indent = Some(indent);
+                found = Some(number + 1);
+                break;
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BROKEN_CONFIG: &str = "\
+living_room_blinds:
+  serial_port: /nonexistent/ttyUSB9
+  slide_motor_id: 3
+  flip_motor_id: 3
+  flip_motor_left: 200.0
+  flip_motor_right: 100.0
+  slide_profile:
+    type: trapezoidal
+    acceleration: 0.0
+  quiet_mode:
+    speed_factor: 1.5
+  mqtt:
+    base_route: ''
+    broker_host: mqtt
+    broker_port: 0
+    client_id: blinds
+http:
+  bind_addresses:
+    - 0.0.0.0
+    - not an address
+  port: 0
+history:
+  capacity: 0
+";
+
+    fn reported(problems: &[ConfigProblem]) -> Vec<(&str, Option<usize>)> {
+        problems
+            .iter()
+            .map(|problem| (problem.path.as_str(), problem.line))
+            .collect()
+    }
+
+    #[test]
+    fn reports_every_problem_with_path_and_line() {
+        let problems = check_config(BROKEN_CONFIG, &ConfigOverrides::default());
+        assert_eq!(
+            reported(&problems),
+            vec![
+                ("living_room_blinds.serial_port", Some(2)),
+                ("living_room_blinds.flip_motor_id", Some(4)),
+                ("living_room_blinds.flip_motor_left", Some(5)),
+                ("living_room_blinds.slide_profile.acceleration", Some(9)),
+                ("living_room_blinds.quiet_mode.speed_factor", Some(11)),
+                ("living_room_blinds.mqtt.base_route", Some(13)),
+                ("living_room_blinds.mqtt.broker_port", Some(15)),
+                ("history.capacity", Some(23)),
+                ("http.bind_addresses", Some(18)),
+                ("http.port", Some(21)),
+            ]
+        );
+        assert_eq!(
+            problems[1].to_string(),
+            "living_room_blinds.flip_motor_id (line 4): flip motor uses the same ID as slide motor 3"
+        );
+    }
+
+    #[test]
+    fn checks_config_with_overrides_applied() {
+        let overrides = ConfigOverrides {
+            broker_port: Some(1883),
+            http_bind_addresses: vec!["127.0.0.1".to_owned()],
+            http_port: Some(8080),
+            ..Default::default()
+        };
+        let problems = check_config(BROKEN_CONFIG, &overrides);
+        let paths: Vec<_> = problems
+            .iter()
+            .map(|problem| problem.path.as_str())
+            .collect();
+        assert!(!paths.contains(&"living_room_blinds.mqtt.broker_port"));
+        assert!(!paths.contains(&"http.bind_addresses"));
+        assert!(!paths.contains(&"http.port"));
+    }
+
+    #[test]
+    fn reports_conflicting_rooms() {
+        let contents = "\
+living_room_blinds:
+  serial_port: /nonexistent/ttyUSB9
+  slide_motor_id: 1
+  flip_motor_id: 2
+  mqtt: {base_route: living_room/blinds, broker_host: mqtt, client_id: blinds}
+# bedroom as well
+bedroom_blinds:
+  serial_port: /nonexistent/ttyUSB9
+  motor_id: 254
+  mqtt: {base_route: bedroom/blinds, broker_host: mqtt, client_id: blinds}
+";
+        let problems = check_config(contents, &ConfigOverrides::default());
+        assert_eq!(
+            reported(&problems),
+            vec![
+                ("bedroom_blinds", Some(7)),
+                ("living_room_blinds.serial_port", Some(2)),
+                ("bedroom_blinds.serial_port", Some(8)),
+                ("bedroom_blinds.motor_id", Some(9)),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_parse_errors_with_line() {
+        let contents = "\
+bedroom_blinds:
+  serial_port: /dev/ttyUSB0
+  motor_id: 1
+  mqtt:
+    base_route: bedroom/blinds
+    broker_host: mqtt
+    broker_port: 70000
+    client_id: blinds
+";
+        let problems = check_config(contents, &ConfigOverrides::default());
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].path, "");
+        assert_eq!(problems[0].line, Some(7));
+        assert!(problems[0].to_string().starts_with("<root> (line 7): "));
+    }
+
+    #[test]
+    fn finds_nested_key_lines() {
+        let contents = "\
+# comment
+http:
+  port: 8080
+
+  tls:
+    cert_file: cert.pem
+history:
+  capacity: 10
+";
+        assert_eq!(find_key_line(contents, "http"), Some(2));
+        assert_eq!(find_key_line(contents, "http.port"), Some(3));
+        assert_eq!(find_key_line(contents, "http.tls.cert_file"), Some(6));
+        assert_eq!(find_key_line(contents, "history.capacity"), Some(8));
+        // key of another mapping
+        assert_eq!(find_key_line(contents, "http.capacity"), None);
+        assert_eq!(find_key_line(contents, "mqtt"), None);
+        assert_eq!(find_key_line(contents, ""), None);
+    }
+
+    #[test]
+    fn bind_addresses_are_ips_or_host_names() {
+        for address in [
+            "0.0.0.0",
+            "::",
+            "192.168.1.10",
+            "localhost",
+            "blinds-pi.lan",
+        ] {
+            assert!(is_valid_bind_address(address), "{address}");
+        }
+        for address in ["", "not an address", "0.0.0.0:8080", "[::1]"] {
+            assert!(!is_valid_bind_address(address), "{address}");
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use crate::{
+    config::MotionMode,
+    routes::{BlindsAction, BlindsCommand},
+};
+use anyhow::Result;
+use blinds_protocol::BlindsStatus;
+use clap::{Args, Subcommand};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Control running service over HTTP
+#[derive(Args, Debug)]
+pub struct CtlArgs {
+    /// address of running service
+    #[clap(long, env = "BLINDS_URL", default_value = "http://localhost:8080")]
+    url: String,
+    /// API token when authentication is enabled
+    #[clap(long, env = "BLINDS_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// print JSON instead of text
+    #[clap(long)]
+    json: bool,
+    #[clap(subcommand)]
+    command: CtlCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum CtlCommand {
+    Open {
+        /// move quietly regardless of quiet hours
+        #[clap(long)]
+        quiet: bool,
+    },
+    Close {
+        #[clap(long)]
+        quiet: bool,
+    },
+    /// open to fraction between 0.0 and 1.0
+    Partial {
+        open: f32,
+        #[clap(long)]
+        quiet: bool,
+    },
+    Toggle {
+        #[clap(long)]
+        quiet: bool,
+    },
+    /// stop running motion
+    Stop,
+    /// print state, with `--watch` until interrupted
+    Status {
+        #[clap(long)]
+        watch: bool,
+        /// seconds between polls while watching
+        #[clap(long, default_value_t = 1.0, parse(try_from_str = parse_interval))]
+        interval: f32,
+    },
+    Calibrate,
+}
+
+/// Longest poll interval, keeps `Duration::from_secs_f32` from overflowing
+const MAX_INTERVAL: f32 = 24.0 * 60.0 * 60.0;
+
+fn parse_interval(value: &str) -> Result<f32, String> {
+    let interval: f32 = value.parse().map_err(|e| format!("{e}"))?;
+    if interval > 0.0 && interval <= MAX_INTERVAL {
+        Ok(interval)
+    } else {
+        Err(format!(
+            "interval has to be more than 0 and at most {MAX_INTERVAL} seconds"
+        ))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CommandOutput<'a> {
+    command: &'a str,
+    success: bool,
+    /// HTTP status code
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl CtlArgs {
+    pub async fn run(&self) -> Result<()> {
+        let client = Client {
+            http: reqwest::Client::new(),
+            url: self.url.trim_end_matches('/').to_owned(),
+            token: self.token.clone(),
+        };
+        let (name, request) = match self.command {
+            CtlCommand::Open { quiet } => ("open", client.command(BlindsAction::Open, quiet)),
+            CtlCommand::Close { quiet } => ("close", client.command(BlindsAction::Close, quiet)),
+            CtlCommand::Partial { open, quiet } => (
+                "partial",
+                client.command(BlindsAction::Partial { open }, quiet),
+            ),
+            CtlCommand::Toggle { quiet } => ("toggle", client.command(BlindsAction::Toggle, quiet)),
+            CtlCommand::Stop => ("stop", client.post("/stop")),
+            CtlCommand::Calibrate => ("calibrate", client.post("/calibrate")),
+            CtlCommand::Status { watch, interval } => {
+                return self.status(&client, watch, interval).await
+            }
+        };
+        let response = request.send().await?;
+        let status = response.status();
+        let error = if status.is_success() {
+            None
+        } else {
+            Some(response.text().await?)
+        };
+        let output = CommandOutput {
+            command: name,
+            success: error.is_none(),
+            status: status.as_u16(),
+            error,
+        };
+        if self.json {
+            println!("{}", serde_json::to_string(&output)?);
+        } else if output.success {
+            println!("{name} done");
+        }
+        if let Some(error) = output.error {
+            anyhow::bail!("{name} failed with {status} {error}");
+        }
+        Ok(())
+    }
+
+    async fn status(&self, client: &Client, watch: bool, interval: f32) -> Result<()> {
+        let mut last: Option<BlindsStatus> = None;
+        loop {
+            let status: BlindsStatus = client
+                .get("/state")
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            // republishing unchanged state only moves the timestamp
+            let changed = match last {
+                Some(ref last) => {
+                    BlindsStatus {
+                        timestamp: last.timestamp,
+                        ..status.clone()
+                    } != *last
+                }
+                None => true,
+            };
+            if changed {
+                if self.json {
+                    println!("{}", serde_json::to_string(&status)?);
+                } else {
+                    print_status(&status);
+                }
+                last = Some(status);
+            }
+            if !watch {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_secs_f32(interval)).await;
+        }
+    }
+}
+
+fn print_status(status: &BlindsStatus) {
+    let mut line = status.state.label().to_owned();
+    if let Some(ref preset) = status.preset {
+        line += &format!(" ({preset})");
+    }
+    if let Some(position) = status.position {
+        line += &format!(" position {position:.2}");
+    }
+    if let Some(tilt) = status.tilt {
+        line += &format!(" tilt {tilt:.2}");
+    }
+    if let Some(target_position) = status.target_position {
+        line += &format!(" moving to {target_position:.2}");
+    }
+    if !status.calibrated {
+        line += " needs calibration";
+    }
+    if let Some(ref error) = status.last_error {
+        line += &format!(" last error {}", error.message);
+    }
+    println!("{line}");
+}
+
+struct Client {
+    http: reqwest::Client,
+    url: String,
+    token: Option<String>,
+}
+
+impl Client {
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        self.authorize(self.http.get(format!("{}{path}", self.url)))
+    }
+
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        self.authorize(self.http.post(format!("{}{path}", self.url)))
+    }
+
+    /// Same wire format as MQTT `command` topic
+    fn command(&self, action: BlindsAction, quiet: bool) -> reqwest::RequestBuilder {
+        self.post("/command").json(&BlindsCommand {
+            action,
+            mode: quiet.then_some(MotionMode::Quiet),
+            correlation_id: None,
+            response_topic: None,
+        })
+    }
+
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.token {
+            Some(ref token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interval_has_to_be_positive() {
+        assert_eq!(parse_interval("0.5"), Ok(0.5));
+        for value in ["0", "-1", "NaN", "inf", "1e30", "soon"] {
+            assert!(parse_interval(value).is_err(), "{value}");
+        }
+    }
+}
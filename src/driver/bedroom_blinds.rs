@@ -1,30 +1,53 @@
 use super::{
-    wait_until_motor_stopped, Blinds, BlindsState, BEDROOM_BLIND_BOTTOM_OFFSET,
+    motion_profile::{follow_plan, ramp_up, LssSpeedControl, MotionPlan},
+    serial::SerialConnection,
+    wait_until_motor_stopped, Blinds, BlindsState, MotionLimits, BEDROOM_BLIND_BOTTOM_OFFSET,
     BEDROOM_DOOR_TOP_OFFSET, BEDROOM_LIFTING_CURRENT_LIMIT, BEDROOM_SLIDING_TIMEOUT,
-    CALIBRATED_COLOR, SLIDING_CURRENT_LIMIT, SLIDING_SPEED, UNCALIBRATED_COLOR,
+    CALIBRATED_COLOR, COMMAND_TIME_MARGIN, SLIDING_CURRENT_LIMIT, SLIDING_SPEED,
+    UNCALIBRATED_COLOR,
+};
+use crate::{
+    config::{BedroomBlindsConfig, BlindsConfig, MotionMode, MotionProfile},
+    error, metrics,
+    mqtt_server::StatePublisher,
 };
-use crate::{config::BedroomBlindsConfig, error, mqtt_server::StatePublisher};
 use anyhow::Result;
 use async_trait::async_trait;
 use log::*;
-use std::path::Path;
+use lss_driver::CommandModifier;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 pub struct BedroomBlinds {
     pub config: BedroomBlindsConfig,
-    driver: lss_driver::LSSDriver,
+    /// Calibration is saved here
+    config_path: PathBuf,
+    driver: SerialConnection,
     state_publisher: Option<StatePublisher>,
     state: BlindsState,
+    /// Motion mode selected by current command
+    mode_override: Option<MotionMode>,
+    /// Motors lost calibration while serial port was disconnected
+    motors_rebooted: bool,
+    /// Height after last finished move
+    position: Option<f32>,
 }
 
 impl BedroomBlinds {
-    pub async fn new(config: BedroomBlindsConfig) -> Result<Self> {
-        let mut serial_driver = lss_driver::LSSDriver::new(&config.serial_port)?;
+    pub async fn new(config: BedroomBlindsConfig, config_path: &Path) -> Result<Self> {
+        let mut serial_driver = SerialConnection::open(&config.serial_port)?;
         serial_driver.limp(lss_driver::BROADCAST_ID).await?;
         Ok(Self {
             config,
+            config_path: config_path.to_owned(),
             driver: serial_driver,
             state_publisher: None,
             state: BlindsState::Other,
+            mode_override: None,
+            motors_rebooted: false,
+            position: None,
         })
     }
 
@@ -38,18 +61,58 @@ impl BedroomBlinds {
         Ok(())
     }
 
+    fn motion_limits(&self) -> MotionLimits {
+        MotionLimits::new(&self.config.quiet_mode, self.mode_override)
+    }
+
     async fn open_until_limit(&mut self) -> Result<()> {
+        let limits = self.motion_limits();
+        let plan = MotionPlan::unbounded(&self.config.lift_profile, -limits.speed(SLIDING_SPEED))?;
+        let mut motor = LssSpeedControl::new(
+            &mut self.driver,
+            self.config.motor_id,
+            limits.current_limit(BEDROOM_LIFTING_CURRENT_LIMIT),
+        );
+        ramp_up(&mut motor, &plan).await?;
+        wait_until_motor_stopped(
+            &mut self.driver,
+            self.config.motor_id,
+            limits.timeout(BEDROOM_SLIDING_TIMEOUT),
+        )
+        .await?;
+        self.driver.limp(self.config.motor_id).await?;
+        Ok(())
+    }
+
+    /// Move to motor position following configured lift profile
+    ///
+    /// Profile runs open loop and final position is held by the motor
+    async fn move_to(&mut self, position: f32, current_limit: u32) -> Result<()> {
+        let limits = self.motion_limits();
+        let speed = limits.speed(SLIDING_SPEED);
+        let current_limit = limits.current_limit(current_limit);
+        if !matches!(self.config.lift_profile, MotionProfile::Constant) {
+            let start = self.driver.query_position(self.config.motor_id).await?;
+            let plan = MotionPlan::new(&self.config.lift_profile, speed, position - start)?;
+            let mut motor =
+                LssSpeedControl::new(&mut self.driver, self.config.motor_id, current_limit);
+            follow_plan(&mut motor, &plan).await?;
+        }
+        // make sure speed is limited
         self.driver
-            .set_rotation_speed_with_modifier(
+            .set_maximum_speed(self.config.motor_id, speed)
+            .await?;
+        self.driver
+            .move_to_position_with_modifier(
                 self.config.motor_id,
-                -SLIDING_SPEED,
-                BEDROOM_LIFTING_CURRENT_LIMIT,
+                position,
+                CommandModifier::CurrentLimp(current_limit),
             )
             .await?;
         wait_until_motor_stopped(
             &mut self.driver,
             self.config.motor_id,
-            BEDROOM_SLIDING_TIMEOUT,
+            limits.timeout(BEDROOM_SLIDING_TIMEOUT),
         )
         .await?;
         self.driver.limp(self.config.motor_id).await?;
@@ -58,9 +121,17 @@ impl BedroomBlinds {
 
     async fn set_state(&mut self, state: BlindsState) -> Result<()> {
         self.state = state;
-        if let Some(ref state_publisher) = self.state_publisher {
-            state_publisher.update_state(state).await?;
+        metrics::record_state(state);
+        if matches!(
+            state,
+            BlindsState::Opening
+                | BlindsState::Closing
+                | BlindsState::Other
+                | BlindsState::Disconnected
+        ) {
+            self.position = None;
         }
+        self.publish_state();
         Ok(())
     }
 }
@@ -68,9 +139,32 @@ impl BedroomBlinds {
 #[async_trait]
 impl Blinds for BedroomBlinds {
     async fn were_motors_rebooted(&mut self) -> Result<bool> {
-        let motor_rebooted =
-            self.driver.query_color(self.config.motor_id).await? != CALIBRATED_COLOR;
-        Ok(motor_rebooted)
+        if self.driver.query_color(self.config.motor_id).await? != CALIBRATED_COLOR {
+            self.motors_rebooted = true;
+        }
+        Ok(self.motors_rebooted)
+    }
+
+    fn reopen_serial_port(&mut self) -> Result<()> {
+        self.driver.reopen_if_moved()
+    }
+
+    async fn set_disconnected(&mut self) -> Result<()> {
+        self.set_state(BlindsState::Disconnected).await
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        self.driver.reopen()?;
+        self.driver.limp(lss_driver::BROADCAST_ID).await?;
+        if self.were_motors_rebooted().await? {
+            warn!("Motors were rebooted while disconnected. Calibration needed");
+        }
+        self.set_state(BlindsState::Other).await
+    }
+
+    async fn check_motors(&mut self) -> Result<()> {
+        self.driver.query_status(self.config.motor_id).await?;
+        Ok(())
     }
 
     async fn open(&mut self) -> Result<()> {
@@ -79,28 +173,15 @@ impl Blinds for BedroomBlinds {
             return Ok(());
         }
         self.set_state(BlindsState::Opening).await?;
-        // make sure speed is limited
-        self.driver
-            .set_maximum_speed(self.config.motor_id, SLIDING_SPEED)
-            .await?;
         // top of bedroom is a bit away from the place where we stop for current limit
-        self.driver
-            .move_to_position_with_modifier(
-                self.config.motor_id,
-                self.config
-                    .top_position
-                    .ok_or(error::DriverError::MissingMotorConfig)?
-                    + BEDROOM_DOOR_TOP_OFFSET,
-                BEDROOM_LIFTING_CURRENT_LIMIT,
-            )
+        let open_position = self
+            .config
+            .top_position
+            .ok_or(error::DriverError::MissingMotorConfig)?
+            + BEDROOM_DOOR_TOP_OFFSET;
+        self.move_to(open_position, BEDROOM_LIFTING_CURRENT_LIMIT)
             .await?;
-        wait_until_motor_stopped(
-            &mut self.driver,
-            self.config.motor_id,
-            BEDROOM_SLIDING_TIMEOUT,
-        )
-        .await?;
-        self.driver.limp(self.config.motor_id).await?;
+        self.position = Some(1.0);
         self.set_state(BlindsState::Open).await?;
         Ok(())
     }
@@ -112,11 +193,7 @@ impl Blinds for BedroomBlinds {
         }
         self.set_state(BlindsState::Opening).await?;
 
-        // make sure speed is limited
-        self.driver
-            .set_maximum_speed(self.config.motor_id, SLIDING_SPEED)
-            .await?;
-
+        // top of bedroom is a bit away from the place where we stop for current limit
         let open_position = self
             .config
             .top_position
@@ -131,21 +208,9 @@ impl Blinds for BedroomBlinds {
 
         let desired_position = closed_position + open * (open_position - closed_position);
 
-        // top of bedroom is a bit away from the place where we stop for current limit
-        self.driver
-            .move_to_position_with_modifier(
-                self.config.motor_id,
-                desired_position,
-                BEDROOM_LIFTING_CURRENT_LIMIT,
-            )
+        self.move_to(desired_position, BEDROOM_LIFTING_CURRENT_LIMIT)
             .await?;
-        wait_until_motor_stopped(
-            &mut self.driver,
-            self.config.motor_id,
-            BEDROOM_SLIDING_TIMEOUT,
-        )
-        .await?;
-        self.driver.limp(self.config.motor_id).await?;
+        self.position = Some(open);
         self.set_state(BlindsState::Partial).await?;
         Ok(())
     }
@@ -156,27 +221,13 @@ impl Blinds for BedroomBlinds {
             return Ok(());
         }
         self.set_state(BlindsState::Closing).await?;
-        // make sure speed is limited
-        self.driver
-            .set_maximum_speed(self.config.motor_id, SLIDING_SPEED)
-            .await?;
-        self.driver
-            .move_to_position_with_modifier(
-                self.config.motor_id,
-                self.config
-                    .top_position
-                    .ok_or(error::DriverError::MissingMotorConfig)?
-                    + BEDROOM_BLIND_BOTTOM_OFFSET,
-                SLIDING_CURRENT_LIMIT,
-            )
-            .await?;
-        wait_until_motor_stopped(
-            &mut self.driver,
-            self.config.motor_id,
-            BEDROOM_SLIDING_TIMEOUT,
-        )
-        .await?;
-        self.driver.limp(self.config.motor_id).await?;
+        let closed_position = self
+            .config
+            .top_position
+            .ok_or(error::DriverError::MissingMotorConfig)?
+            + BEDROOM_BLIND_BOTTOM_OFFSET;
+        self.move_to(closed_position, SLIDING_CURRENT_LIMIT).await?;
+        self.position = Some(0.0);
         self.set_state(BlindsState::Closed).await?;
         Ok(())
     }
@@ -188,28 +239,101 @@ impl Blinds for BedroomBlinds {
             BlindsState::Open
             | BlindsState::Opening
             | BlindsState::Other
+            | BlindsState::Disconnected
+            | BlindsState::Unknown
             | BlindsState::Partial => self.close().await?,
         }
         Ok(())
     }
 
-    async fn calibrate(&mut self, config_path: &Path) -> Result<()> {
+    async fn apply_preset(&mut self, name: &str) -> Result<()> {
+        let preset = *self
+            .config
+            .presets
+            .get(name)
+            .ok_or_else(|| error::DriverError::UnknownPreset(name.to_owned()))?;
+        info!("Moving to preset {name}");
+        self.partial_open(preset.height).await
+    }
+
+    async fn set_position(&mut self, position: f32) -> Result<()> {
+        self.partial_open(position).await
+    }
+
+    async fn calibrate(&mut self) -> Result<()> {
         self.set_state(BlindsState::Other).await?;
         info!("Starting calibration for bedroom blinds");
         self.open_until_limit().await?;
         let top_position = self.driver.query_position(self.config.motor_id).await?;
         self.config.top_position = Some(top_position);
-        self.config.save(config_path).await?;
+        self.config.save_calibration(&self.config_path).await?;
         self.configure().await?;
+        self.motors_rebooted = false;
+        metrics::record_calibration();
         self.open().await?;
         Ok(())
     }
 
+    fn max_command_time(&self) -> Duration {
+        // calibration lifts to the limit and opens again. Profile moves can take
+        // as long as the wait for stop that follows them
+        let move_time =
+            MotionLimits::slowest(&self.config.quiet_mode).timeout(BEDROOM_SLIDING_TIMEOUT);
+        4 * move_time + COMMAND_TIME_MARGIN
+    }
+
     fn needs_calibration(&self) -> bool {
-        self.config.top_position.is_none()
+        self.config.top_position.is_none() || self.motors_rebooted
+    }
+
+    fn state(&self) -> BlindsState {
+        self.state
+    }
+
+    fn position(&self) -> Option<f32> {
+        self.position
+    }
+
+    fn preset(&self) -> Option<String> {
+        self.config
+            .preset_at(self.position?)
+            .map(|name| name.to_owned())
+    }
+
+    fn update_config(&mut self, config: &BlindsConfig) -> Result<()> {
+        let new_config = config
+            .bedroom_blinds()
+            .ok_or(error::DriverError::RoomTypeChanged)?;
+        new_config.quiet_mode.validate()?;
+        new_config.lift_profile.validate("lift_profile")?;
+        if new_config.serial_port != self.config.serial_port
+            || new_config.motor_id != self.config.motor_id
+        {
+            warn!("Serial port and motor ID changes require a restart. Ignoring them");
+        }
+        self.config = BedroomBlindsConfig {
+            serial_port: self.config.serial_port.clone(),
+            motor_id: self.config.motor_id,
+            top_position: self.config.top_position,
+            ..new_config.clone()
+        };
+        Ok(())
+    }
+
+    fn set_motion_mode(&mut self, mode: Option<MotionMode>) {
+        self.mode_override = mode;
     }
 
     fn set_state_publisher(&mut self, state_publisher: StatePublisher) {
         self.state_publisher = Some(state_publisher)
     }
+
+    fn publish_state(&self) {
+        if let Some(ref state_publisher) = self.state_publisher {
+            // don't fail motion because broker is unreachable
+            if let Err(e) = state_publisher.publish(self) {
+                warn!("Failed to publish state {e}");
+            }
+        }
+    }
 }
@@ -0,0 +1,178 @@
+use super::{
+    serial::SerialConnection, wait_until_motor_stopped, SLIDING_CURRENT_LIMIT, SLIDING_SPEED,
+};
+use crate::config::{BlindsConfig, SerialPortConfig};
+use anyhow::Result;
+use clap::Subcommand;
+use log::*;
+use lss_driver::CommandModifier;
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Motors answer within a few milliseconds. Missing ones never do
+const PROBE_TIMEOUT: Duration = Duration::from_millis(100);
+/// Highest ID that can be assigned to LSS motor
+const MAX_MOTOR_ID: u8 = 250;
+const MANUAL_MOVE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Motor bus tools for setting up new blinds
+#[derive(Subcommand, Debug)]
+pub enum DiagnosticsCommand {
+    /// probe motor IDs on the bus
+    Scan {
+        /// first ID to probe
+        #[clap(long, default_value_t = 0)]
+        first: u8,
+        /// last ID to probe
+        #[clap(long, default_value_t = MAX_MOTOR_ID)]
+        last: u8,
+    },
+    /// print status of configured motors
+    Status,
+    /// move motor to position in degrees
+    Move {
+        id: u8,
+        #[clap(allow_hyphen_values = true)]
+        position: f32,
+        /// move by position from current position
+        #[clap(long)]
+        relative: bool,
+        /// current limit in mA
+        #[clap(long, default_value_t = SLIDING_CURRENT_LIMIT)]
+        current_limit: u32,
+    },
+    /// release motor, all motors if no ID is given
+    Limp { id: Option<u8> },
+    /// change ID of motor
+    SetId { id: u8, new_id: u8 },
+}
+
+impl DiagnosticsCommand {
+    /// Run command on serial port of config
+    ///
+    /// Service should be stopped first because the port can only be opened once
+    pub async fn run(&self, config: &BlindsConfig) -> Result<()> {
+        let serial_port = serial_port(config);
+        let mut driver = SerialConnection::open(&serial_port)?;
+        match *self {
+            DiagnosticsCommand::Scan { first, last } => scan(&mut driver, first, last).await,
+            DiagnosticsCommand::Status => status(&mut driver, config).await,
+            DiagnosticsCommand::Move {
+                id,
+                position,
+                relative,
+                current_limit,
+            } => move_motor(&mut driver, id, position, relative, current_limit).await,
+            DiagnosticsCommand::Limp { id } => {
+                driver.limp(id.unwrap_or(lss_driver::BROADCAST_ID)).await?;
+                Ok(())
+            }
+            DiagnosticsCommand::SetId { id, new_id } => set_id(&mut driver, id, new_id).await,
+        }
+    }
+}
+
+fn serial_port(config: &BlindsConfig) -> SerialPortConfig {
+    match (config.living_room_blinds(), config.bedroom_blinds()) {
+        (Some(living_room_blinds), _) => living_room_blinds.serial_port.clone(),
+        (None, Some(bedroom_blinds)) => bedroom_blinds.serial_port.clone(),
+        (None, None) => SerialPortConfig::default(),
+    }
+}
+
+/// Motor names and IDs of configured room
+fn configured_motors(config: &BlindsConfig) -> Vec<(&'static str, u8)> {
+    let mut motors = vec![];
+    if let Some(living_room_blinds) = config.living_room_blinds() {
+        motors.push(("slide", living_room_blinds.slide_motor_id));
+        motors.push(("flip", living_room_blinds.flip_motor_id));
+    }
+    if let Some(bedroom_blinds) = config.bedroom_blinds() {
+        motors.push(("lift", bedroom_blinds.motor_id));
+    }
+    motors
+}
+
+async fn scan(driver: &mut SerialConnection, first: u8, last: u8) -> Result<()> {
+    let mut found = 0;
+    for id in first..=last.min(MAX_MOTOR_ID) {
+        match timeout(PROBE_TIMEOUT, driver.query_status(id)).await {
+            Ok(Ok(_)) => (),
+            Ok(Err(e)) => {
+                debug!("Motor {id} didn't answer {e}");
+                continue;
+            }
+            Err(_) => continue,
+        }
+        found += 1;
+        let model = driver.query_model(id).await?;
+        let firmware = driver.query_firmware_version(id).await?;
+        let voltage = driver.query_voltage(id).await?;
+        let color = driver.query_color(id).await?;
+        println!(
+            "ID {id:>3}  model {model:?}  firmware {firmware:?}  voltage {voltage:.1}V  color {color:?}"
+        );
+    }
+    println!("Found {found} motors");
+    Ok(())
+}
+
+async fn status(driver: &mut SerialConnection, config: &BlindsConfig) -> Result<()> {
+    let motors = configured_motors(config);
+    if motors.is_empty() {
+        anyhow::bail!("no blinds configured");
+    }
+    for (name, id) in motors {
+        match timeout(PROBE_TIMEOUT, driver.query_status(id)).await {
+            Ok(Ok(status)) => {
+                let position = driver.query_position(id).await?;
+                let voltage = driver.query_voltage(id).await?;
+                let color = driver.query_color(id).await?;
+                println!(
+                    "{name:<5} ID {id:>3}  status {status:?}  position {position:.1}  voltage {voltage:.1}V  color {color:?}"
+                );
+            }
+            Ok(Err(e)) => println!("{name:<5} ID {id:>3}  error {e}"),
+            Err(_) => println!("{name:<5} ID {id:>3}  not answering"),
+        }
+    }
+    Ok(())
+}
+
+async fn move_motor(
+    driver: &mut SerialConnection,
+    id: u8,
+    position: f32,
+    relative: bool,
+    current_limit: u32,
+) -> Result<()> {
+    let start = driver.query_position(id).await?;
+    let target = if relative { start + position } else { position };
+    info!("Moving motor {id} from {start:.1} to {target:.1}");
+    driver.set_maximum_speed(id, SLIDING_SPEED).await?;
+    driver
+        .move_to_position_with_modifier(id, target, CommandModifier::CurrentLimp(current_limit))
+        .await?;
+    let result = wait_until_motor_stopped(driver, id, MANUAL_MOVE_TIMEOUT).await;
+    println!("Motor {id} at {:.1}", driver.query_position(id).await?);
+    result
+}
+
+/// Configured ID only takes effect after motor is reset
+async fn set_id(driver: &mut SerialConnection, id: u8, new_id: u8) -> Result<()> {
+    if new_id > MAX_MOTOR_ID {
+        anyhow::bail!("motor ID has to be at most {MAX_MOTOR_ID}, got {new_id}");
+    }
+    if matches!(
+        timeout(PROBE_TIMEOUT, driver.query_status(new_id)).await,
+        Ok(Ok(_))
+    ) {
+        anyhow::bail!("motor with ID {new_id} is already on the bus");
+    }
+    driver.configure_id(id, new_id).await?;
+    driver.reset(id).await?;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    driver.query_status(new_id).await?;
+    println!("Motor {id} now has ID {new_id}");
+    Ok(())
+}
@@ -1,30 +1,61 @@
 use super::{
-    wait_until_motor_stopped, Blinds, BlindsState, CALIBRATED_COLOR, LIVING_ROOM_FLIPPER_TIMEOUT,
-    LIVING_ROOM_SLIDING_TIMEOUT, SLIDING_CURRENT_LIMIT, SLIDING_SPEED, UNCALIBRATED_COLOR,
+    check_stop,
+    motion_profile::{follow_plan, ramp_up, LssSpeedControl, MotionPlan},
+    serial::SerialConnection,
+    wait_until_motor_stopped, Blinds, BlindsState, MotionLimits, CALIBRATED_COLOR,
+    COMMAND_TIME_MARGIN, FLIPPER_CALIBRATION_START_TIMEOUT, FLIPPER_CALIBRATION_TIME,
+    LIVING_ROOM_FLIPPER_TIMEOUT, LIVING_ROOM_SLIDING_TIMEOUT, SLIDE_POSITION_TOLERANCE,
+    SLIDING_CURRENT_LIMIT, SLIDING_SPEED, UNCALIBRATED_COLOR,
+};
+use crate::{
+    config::{BlindsConfig, LivingRoomBlindsConfig, MotionMode, MotionProfile},
+    error, metrics,
+    mqtt_server::StatePublisher,
 };
-use crate::{config::LivingRoomBlindsConfig, error, mqtt_server::StatePublisher};
 use anyhow::Result;
 use async_trait::async_trait;
 use log::*;
-use std::{path::Path, time::Duration};
+use lss_driver::CommandModifier;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use tokio::time::sleep;
 
+/// Flip motor within this many degrees of target is considered in place
+const FLIP_POSITION_TOLERANCE: f32 = 3.0;
+
 pub struct LivingRoomBlinds {
     pub config: LivingRoomBlindsConfig,
-    driver: lss_driver::LSSDriver,
+    /// Calibration is saved here
+    config_path: PathBuf,
+    driver: SerialConnection,
     state_publisher: Option<StatePublisher>,
     state: BlindsState,
+    /// Motion mode selected by current command
+    mode_override: Option<MotionMode>,
+    /// Motors lost calibration while serial port was disconnected
+    motors_rebooted: bool,
+    /// Slide after last finished move with 1.0 being fully open
+    slide: Option<f32>,
+    /// Tilt after last finished move, see [`LivingRoomBlinds::flip_tilt`]
+    tilt: Option<f32>,
 }
 
 impl LivingRoomBlinds {
-    pub async fn new(config: LivingRoomBlindsConfig) -> Result<Self> {
-        let mut serial_driver = lss_driver::LSSDriver::new(&config.serial_port)?;
+    pub async fn new(config: LivingRoomBlindsConfig, config_path: &Path) -> Result<Self> {
+        let mut serial_driver = SerialConnection::open(&config.serial_port)?;
         serial_driver.limp(lss_driver::BROADCAST_ID).await?;
         Ok(Self {
             config,
+            config_path: config_path.to_owned(),
             driver: serial_driver,
             state_publisher: None,
             state: BlindsState::Other,
+            mode_override: None,
+            motors_rebooted: false,
+            slide: None,
+            tilt: None,
         })
     }
 
@@ -47,21 +78,51 @@ impl LivingRoomBlinds {
     }
 
     pub async fn flip_open(&mut self) -> Result<()> {
+        self.flip_tilt(0.0).await
+    }
+
+    pub async fn flip_close_left(&mut self) -> Result<()> {
+        self.flip_tilt(-1.0).await
+    }
+
+    /// Tilt slats
+    ///
+    /// # Arguments
+    ///
+    /// * `tilt` - number between -1.0 and 1.0 with -1.0 being closed to the left,
+    ///   0.0 open and 1.0 closed to the right
+    pub async fn flip_tilt(&mut self, tilt: f32) -> Result<()> {
+        if !(-1.0..=1.0).contains(&tilt) {
+            error!("Tilt has to be between -1.0 and 1.0, got {}", tilt);
+            return Err(error::DriverError::PartialPositionOutOfRange.into());
+        }
+        // commands chain several moves and stop has to end all of them
+        check_stop()?;
+
         let flip_motor_center = self
             .config
             .flip_motor_center()
             .ok_or(error::DriverError::MissingMotorConfig)?;
 
+        let fully_closed = if tilt < 0.0 {
+            self.config.flip_motor_left
+        } else {
+            self.config.flip_motor_right
+        }
+        .ok_or(error::DriverError::MissingMotorConfig)?;
+
+        let desired_position = flip_motor_center + tilt.abs() * (fully_closed - flip_motor_center);
+
         let current_position = self
             .driver
             .query_position(self.config.flip_motor_id)
             .await?;
 
-        let delta = (current_position - flip_motor_center).abs();
-        if delta < 3.0 {
+        let delta = (current_position - desired_position).abs();
+        if delta < FLIP_POSITION_TOLERANCE {
             info!(
-                "Flip motor already opened pose {} center {}",
-                current_position, flip_motor_center
+                "Flip motor already tilted current pose {} desired {}",
+                current_position, desired_position
             );
             self.driver.limp(self.config.flip_motor_id).await?;
             return Ok(());
@@ -70,8 +131,8 @@ impl LivingRoomBlinds {
         self.driver
             .move_to_position_with_modifier(
                 self.config.flip_motor_id,
-                flip_motor_center,
-                SLIDING_CURRENT_LIMIT,
+                desired_position,
+                CommandModifier::CurrentLimp(SLIDING_CURRENT_LIMIT),
             )
             .await?;
         wait_until_motor_stopped(
@@ -84,134 +145,186 @@ impl LivingRoomBlinds {
         Ok(())
     }
 
-    pub async fn flip_close_left(&mut self) -> Result<()> {
-        self.driver
-            .move_to_position_with_modifier(
-                self.config.flip_motor_id,
-                self.config
-                    .flip_motor_left
-                    .ok_or(error::DriverError::MissingMotorConfig)?,
-                SLIDING_CURRENT_LIMIT,
-            )
-            .await?;
-        wait_until_motor_stopped(
-            &mut self.driver,
-            self.config.flip_motor_id,
-            LIVING_ROOM_FLIPPER_TIMEOUT,
-        )
-        .await?;
-        self.driver.limp(self.config.flip_motor_id).await?;
-        Ok(())
+    pub async fn slide_open(&mut self) -> Result<()> {
+        self.slide_until_stopped(-SLIDING_SPEED).await
     }
 
-    pub async fn flip_partial_left(&mut self, open: f32) -> Result<()> {
-        if !(0.0..=1.0).contains(&open) {
-            error!("Open has to be between 0.0 and 1.0, got {}", open);
-            return Err(error::DriverError::PartialPositionOutOfRange.into());
-        }
+    pub async fn slide_closed(&mut self) -> Result<()> {
+        self.slide_until_stopped(SLIDING_SPEED).await
+    }
 
+    fn motion_limits(&self) -> MotionLimits {
+        MotionLimits::new(&self.config.quiet_mode, self.mode_override)
+    }
+
+    /// Slats are open according to last move or flip motor position
+    async fn slats_open(&mut self) -> Result<bool> {
+        if let Some(tilt) = self.tilt {
+            return Ok(tilt.abs() < f32::EPSILON);
+        }
         let flip_motor_center = self
             .config
             .flip_motor_center()
             .ok_or(error::DriverError::MissingMotorConfig)?;
-
-        let fully_closed = self
-            .config
-            .flip_motor_left
-            .ok_or(error::DriverError::MissingMotorConfig)?;
-
-        let desired_position = fully_closed + open * (flip_motor_center - fully_closed);
-
         let current_position = self
             .driver
             .query_position(self.config.flip_motor_id)
             .await?;
+        Ok((current_position - flip_motor_center).abs() < FLIP_POSITION_TOLERANCE)
+    }
 
-        let delta = (current_position - desired_position).abs();
-        if delta < 3.0 {
-            info!(
-                "Flip motor already partially opened current pose {} desired {}",
-                current_position, desired_position
-            );
-            self.driver.limp(self.config.flip_motor_id).await?;
-            return Ok(());
-        }
-
-        self.driver
-            .move_to_position_with_modifier(
-                self.config.flip_motor_id,
-                desired_position,
-                SLIDING_CURRENT_LIMIT,
-            )
-            .await?;
+    /// Slide against end stop with configured ramp up
+    async fn slide_until_stopped(&mut self, speed: f32) -> Result<()> {
+        let limits = self.motion_limits();
+        let plan = MotionPlan::unbounded(&self.config.slide_profile, limits.speed(speed))?;
+        let mut motor = LssSpeedControl::new(
+            &mut self.driver,
+            self.config.slide_motor_id,
+            limits.current_limit(SLIDING_CURRENT_LIMIT),
+        );
+        ramp_up(&mut motor, &plan).await?;
         wait_until_motor_stopped(
             &mut self.driver,
-            self.config.flip_motor_id,
-            LIVING_ROOM_FLIPPER_TIMEOUT,
+            self.config.slide_motor_id,
+            limits.timeout(LIVING_ROOM_SLIDING_TIMEOUT),
         )
         .await?;
-        self.driver.limp(self.config.flip_motor_id).await?;
+        self.driver.limp(self.config.slide_motor_id).await?;
         Ok(())
     }
 
-    #[allow(dead_code)]
-    // TODO(David): I don't think I want to do this almost ever
-    pub async fn flip_close_right(&mut self) -> Result<()> {
-        self.driver
-            .move_to_position_with_modifier(
-                self.config.flip_motor_id,
-                self.config
-                    .flip_motor_right
-                    .ok_or(error::DriverError::MissingMotorConfig)?,
-                SLIDING_CURRENT_LIMIT,
-            )
+    /// Convert slide motor position to fraction of travel with 1.0 being fully open
+    fn slide_fraction(&self, motor_position: f32) -> Result<f32> {
+        let closed = self
+            .config
+            .slide_closed_position
+            .ok_or(error::DriverError::MissingMotorConfig)?;
+        let open = self
+            .config
+            .slide_open_position
+            .ok_or(error::DriverError::MissingMotorConfig)?;
+        Ok((motor_position - closed) / (open - closed))
+    }
+
+    /// Read curtain position from slide motor
+    async fn query_slide(&mut self) -> Result<f32> {
+        let motor_position = self
+            .driver
+            .query_position(self.config.slide_motor_id)
             .await?;
-        wait_until_motor_stopped(
-            &mut self.driver,
-            self.config.flip_motor_id,
-            LIVING_ROOM_FLIPPER_TIMEOUT,
-        )
-        .await?;
-        self.driver.limp(self.config.flip_motor_id).await?;
-        Ok(())
+        self.slide_fraction(motor_position)
     }
 
-    pub async fn slide_open(&mut self) -> Result<()> {
+    /// Slide curtain to a fraction of its travel
+    ///
+    /// End positions run against the end stops. Positions in between use calibrated
+    /// slide motor positions and are verified after the move
+    async fn slide_to(&mut self, position: f32) -> Result<()> {
+        if position >= 1.0 {
+            return self.slide_open().await;
+        }
+        if position <= 0.0 {
+            return self.slide_closed().await;
+        }
+        let closed = self
+            .config
+            .slide_closed_position
+            .ok_or(error::DriverError::MissingMotorConfig)?;
+        let open = self
+            .config
+            .slide_open_position
+            .ok_or(error::DriverError::MissingMotorConfig)?;
+        let desired_position = closed + position * (open - closed);
+        let limits = self.motion_limits();
+        let speed = limits.speed(SLIDING_SPEED);
+        let current_limit = limits.current_limit(SLIDING_CURRENT_LIMIT);
+
+        if !matches!(self.config.slide_profile, MotionProfile::Constant) {
+            let start = self
+                .driver
+                .query_position(self.config.slide_motor_id)
+                .await?;
+            let plan =
+                MotionPlan::new(&self.config.slide_profile, speed, desired_position - start)?;
+            let mut motor =
+                LssSpeedControl::new(&mut self.driver, self.config.slide_motor_id, current_limit);
+            follow_plan(&mut motor, &plan).await?;
+        }
+
+        // make sure speed is limited
         self.driver
-            .set_rotation_speed_with_modifier(
+            .set_maximum_speed(self.config.slide_motor_id, speed)
+            .await?;
+        self.driver
+            .move_to_position_with_modifier(
                 self.config.slide_motor_id,
-                -SLIDING_SPEED,
-                SLIDING_CURRENT_LIMIT,
+                desired_position,
+                CommandModifier::CurrentLimp(current_limit),
             )
             .await?;
         wait_until_motor_stopped(
             &mut self.driver,
             self.config.slide_motor_id,
-            LIVING_ROOM_SLIDING_TIMEOUT,
+            limits.timeout(LIVING_ROOM_SLIDING_TIMEOUT),
         )
         .await?;
         self.driver.limp(self.config.slide_motor_id).await?;
+
+        // current limit stops the motor early if curtain gets stuck
+        let reached = self.query_slide().await?;
+        if (reached - position).abs() > SLIDE_POSITION_TOLERANCE {
+            error!("Slide stopped at {reached} instead of {position}");
+            return Err(error::DriverError::SlidePositionNotReached {
+                expected: position,
+                actual: reached,
+            }
+            .into());
+        }
         Ok(())
     }
 
-    pub async fn slide_closed(&mut self) -> Result<()> {
-        self.driver
-            .set_rotation_speed_with_modifier(
-                self.config.slide_motor_id,
-                SLIDING_SPEED,
-                SLIDING_CURRENT_LIMIT,
-            )
+    /// Record slide motor positions at both end stops
+    async fn calibrate_slide(&mut self) -> Result<()> {
+        self.slide_closed().await?;
+        let closed = self
+            .driver
+            .query_position(self.config.slide_motor_id)
             .await?;
-        wait_until_motor_stopped(
-            &mut self.driver,
-            self.config.slide_motor_id,
-            LIVING_ROOM_SLIDING_TIMEOUT,
-        )
-        .await?;
-        self.driver.limp(self.config.slide_motor_id).await?;
+        self.slide_open().await?;
+        let open = self
+            .driver
+            .query_position(self.config.slide_motor_id)
+            .await?;
+        info!("Slide closed: {closed}, open: {open}");
+        self.config.slide_closed_position = Some(closed);
+        self.config.slide_open_position = Some(open);
+        self.slide_closed().await?;
         Ok(())
     }
 
+    /// Calibrate slide with open slats and save both motors' calibration
+    async fn calibrate_slide_and_save(&mut self) -> Result<()> {
+        self.flip_open().await?;
+        self.calibrate_slide().await?;
+        sleep(Duration::from_secs(2)).await;
+        self.flip_close_left().await?;
+        self.config.save_calibration(&self.config_path).await?;
+        self.configure().await?;
+        self.motors_rebooted = false;
+        metrics::record_calibration();
+        Ok(())
+    }
+
+    fn state_at(slide: f32, tilt: f32) -> BlindsState {
+        if slide >= 1.0 {
+            BlindsState::Open
+        } else if slide <= 0.0 && tilt.abs() >= 1.0 {
+            BlindsState::Closed
+        } else {
+            BlindsState::Partial
+        }
+    }
+
     pub async fn calibrate_flipper(&mut self) -> Result<()> {
         let start_color = self.driver.query_color(self.config.flip_motor_id).await?;
         let start_pose = self
@@ -236,6 +349,19 @@ impl LivingRoomBlinds {
                 info!("Detected started moving");
                 break;
             }
+            if let Err(e) = check_stop() {
+                self.driver
+                    .set_color(self.config.flip_motor_id, start_color)
+                    .await?;
+                return Err(e);
+            }
+            if move_detection_start.elapsed() > FLIPPER_CALIBRATION_START_TIMEOUT {
+                error!("Flip motor wasn't moved to start calibration");
+                self.driver
+                    .set_color(self.config.flip_motor_id, start_color)
+                    .await?;
+                return Err(error::DriverError::CalibrationNotStarted.into());
+            }
             // blink
             if move_detection_start.elapsed().as_secs() % 2 == 0 {
                 self.driver
@@ -255,7 +381,13 @@ impl LivingRoomBlinds {
             .set_color(self.config.flip_motor_id, lss_driver::LedColor::Green)
             .await?;
         let detection_loop_start = std::time::Instant::now();
-        while detection_loop_start.elapsed() < Duration::from_secs(20) {
+        while detection_loop_start.elapsed() < FLIPPER_CALIBRATION_TIME {
+            if let Err(e) = check_stop() {
+                self.driver
+                    .set_color(self.config.flip_motor_id, start_color)
+                    .await?;
+                return Err(e);
+            }
             let current_pose = self
                 .driver
                 .query_position(self.config.flip_motor_id)
@@ -279,9 +411,18 @@ impl LivingRoomBlinds {
 
     async fn set_state(&mut self, state: BlindsState) -> Result<()> {
         self.state = state;
-        if let Some(ref state_publisher) = self.state_publisher {
-            state_publisher.update_state(state).await?;
+        metrics::record_state(state);
+        if matches!(
+            state,
+            BlindsState::Opening
+                | BlindsState::Closing
+                | BlindsState::Other
+                | BlindsState::Disconnected
+        ) {
+            self.slide = None;
+            self.tilt = None;
         }
+        self.publish_state();
         Ok(())
     }
 }
@@ -293,7 +434,33 @@ impl Blinds for LivingRoomBlinds {
             self.driver.query_color(self.config.flip_motor_id).await? != CALIBRATED_COLOR;
         let slide_motor_rebooted =
             self.driver.query_color(self.config.slide_motor_id).await? != CALIBRATED_COLOR;
-        Ok(flip_motor_rebooted || slide_motor_rebooted)
+        if flip_motor_rebooted || slide_motor_rebooted {
+            self.motors_rebooted = true;
+        }
+        Ok(self.motors_rebooted)
+    }
+
+    fn reopen_serial_port(&mut self) -> Result<()> {
+        self.driver.reopen_if_moved()
+    }
+
+    async fn set_disconnected(&mut self) -> Result<()> {
+        self.set_state(BlindsState::Disconnected).await
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        self.driver.reopen()?;
+        self.driver.limp(lss_driver::BROADCAST_ID).await?;
+        if self.were_motors_rebooted().await? {
+            warn!("Motors were rebooted while disconnected. Calibration needed");
+        }
+        self.set_state(BlindsState::Other).await
+    }
+
+    async fn check_motors(&mut self) -> Result<()> {
+        self.driver.query_status(self.config.slide_motor_id).await?;
+        self.driver.query_status(self.config.flip_motor_id).await?;
+        Ok(())
     }
 
     async fn open(&mut self) -> Result<()> {
@@ -304,24 +471,23 @@ impl Blinds for LivingRoomBlinds {
         self.set_state(BlindsState::Opening).await?;
         self.flip_open().await?;
         self.slide_open().await?;
+        self.slide = Some(1.0);
+        self.tilt = Some(0.0);
         self.set_state(BlindsState::Open).await?;
         Ok(())
     }
 
     async fn partial_open(&mut self, open: f32) -> Result<()> {
-        self.set_state(BlindsState::Opening).await?;
-        match self.state {
-            BlindsState::Open => {
-                self.flip_open().await?;
-                self.slide_closed().await?;
-            }
-            BlindsState::Closed => {
-                self.flip_open().await?;
-            }
-            _ => (),
+        if !(0.0..=1.0).contains(&open) {
+            error!("Open has to be between 0.0 and 1.0, got {}", open);
+            return Err(error::DriverError::PartialPositionOutOfRange.into());
         }
-        self.flip_partial_left(open).await?;
-        self.set_state(BlindsState::Partial).await?;
+        self.set_state(BlindsState::Opening).await?;
+        self.flip_open().await?;
+        self.slide_to(open).await?;
+        self.slide = Some(open);
+        self.tilt = Some(0.0);
+        self.set_state(Self::state_at(open, 0.0)).await?;
         Ok(())
     }
 
@@ -334,6 +500,8 @@ impl Blinds for LivingRoomBlinds {
         self.flip_open().await?;
         self.slide_closed().await?;
         self.flip_close_left().await?;
+        self.slide = Some(0.0);
+        self.tilt = Some(-1.0);
         self.set_state(BlindsState::Closed).await?;
         Ok(())
     }
@@ -345,28 +513,189 @@ impl Blinds for LivingRoomBlinds {
             BlindsState::Open
             | BlindsState::Opening
             | BlindsState::Other
+            | BlindsState::Disconnected
+            | BlindsState::Unknown
             | BlindsState::Partial => self.close().await?,
         }
         Ok(())
     }
 
-    async fn calibrate(&mut self, config_path: &Path) -> Result<()> {
+    async fn apply_preset(&mut self, name: &str) -> Result<()> {
+        let preset = *self
+            .config
+            .presets
+            .get(name)
+            .ok_or_else(|| error::DriverError::UnknownPreset(name.to_owned()))?;
+        info!("Moving to preset {name}");
+        if preset.slide >= 1.0 {
+            return self.open().await;
+        }
+        self.partial_open(preset.slide).await?;
+        if preset.slide <= 0.0 {
+            self.set_tilt(preset.tilt - 1.0).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_position(&mut self, position: f32) -> Result<()> {
+        if !(0.0..=1.0).contains(&position) {
+            error!("Position has to be between 0.0 and 1.0, got {}", position);
+            return Err(error::DriverError::PartialPositionOutOfRange.into());
+        }
+        // sliding with closed slats drags them along the window
+        if !self.slats_open().await? {
+            return Err(error::DriverError::SlatsNotOpen.into());
+        }
+        let from = match self.slide {
+            Some(slide) => Some(slide),
+            None => self.query_slide().await.ok(),
+        };
+        let opening = match from {
+            Some(from) => position > from,
+            None => true,
+        };
+        if opening {
+            self.set_state(BlindsState::Opening).await?;
+        } else {
+            self.set_state(BlindsState::Closing).await?;
+        }
+        self.slide_to(position).await?;
+        self.slide = Some(position);
+        self.tilt = Some(0.0);
+        self.set_state(Self::state_at(position, 0.0)).await?;
+        Ok(())
+    }
+
+    async fn set_tilt(&mut self, tilt: f32) -> Result<()> {
+        if !(-1.0..=1.0).contains(&tilt) {
+            error!("Tilt has to be between -1.0 and 1.0, got {}", tilt);
+            return Err(error::DriverError::PartialPositionOutOfRange.into());
+        }
+        // opening slats is always safe, closing them only once curtain is closed
+        let curtain_closed = matches!(self.slide, Some(slide) if slide <= 0.0);
+        if tilt.abs() > f32::EPSILON && !curtain_closed {
+            return Err(error::DriverError::CurtainNotClosed.into());
+        }
+        let slide = self.slide;
+        if tilt.abs() < self.tilt.map_or(1.0, f32::abs) {
+            self.set_state(BlindsState::Opening).await?;
+        } else {
+            self.set_state(BlindsState::Closing).await?;
+        }
+        self.flip_tilt(tilt).await?;
+        match slide {
+            Some(slide) => {
+                self.slide = Some(slide);
+                self.tilt = Some(tilt);
+                self.set_state(Self::state_at(slide, tilt)).await?;
+            }
+            None => {
+                self.set_state(BlindsState::Other).await?;
+                // curtain position stays unknown but slats can be used for sliding
+                self.tilt = Some(tilt);
+            }
+        }
+        Ok(())
+    }
+
+    async fn calibrate(&mut self) -> Result<()> {
         self.set_state(BlindsState::Other).await?;
         info!("Starting calibration for living room blinds");
         self.calibrate_flipper().await?;
-        self.flip_open().await?;
-        sleep(Duration::from_secs(2)).await;
-        self.flip_close_left().await?;
-        self.config.save(config_path).await?;
-        self.configure().await?;
-        Ok(())
+        self.calibrate_slide_and_save().await
+    }
+
+    async fn calibrate_unattended(&mut self) -> Result<()> {
+        let flipper_calibrated = self.config.flip_motor_left.is_some()
+            && self.config.flip_motor_right.is_some()
+            && !self.motors_rebooted;
+        if !flipper_calibrated {
+            // nobody may be there to move the slats
+            return Err(error::DriverError::ManualCalibrationNeeded.into());
+        }
+        self.set_state(BlindsState::Other).await?;
+        info!("Flip motor is calibrated. Calibrating slide of living room blinds");
+        self.calibrate_slide_and_save().await
+    }
+
+    fn max_command_time(&self) -> Duration {
+        // calibration waits for flipper to be moved by hand, slides three times
+        // and tilts twice with a pause in between
+        let slide_time =
+            MotionLimits::slowest(&self.config.quiet_mode).timeout(LIVING_ROOM_SLIDING_TIMEOUT);
+        FLIPPER_CALIBRATION_START_TIMEOUT
+            + FLIPPER_CALIBRATION_TIME
+            + 3 * slide_time
+            + 2 * LIVING_ROOM_FLIPPER_TIMEOUT
+            + COMMAND_TIME_MARGIN
     }
 
     fn needs_calibration(&self) -> bool {
-        self.config.flip_motor_left.is_none() || self.config.flip_motor_right.is_none()
+        self.config.flip_motor_left.is_none()
+            || self.config.flip_motor_right.is_none()
+            || self.config.slide_closed_position.is_none()
+            || self.config.slide_open_position.is_none()
+            || self.motors_rebooted
+    }
+
+    fn state(&self) -> BlindsState {
+        self.state
+    }
+
+    fn position(&self) -> Option<f32> {
+        self.slide
+    }
+
+    fn tilt(&self) -> Option<f32> {
+        self.tilt
+    }
+
+    fn preset(&self) -> Option<String> {
+        // presets store tilt as fraction of left tilt
+        self.config
+            .preset_at(self.slide?, self.tilt? + 1.0)
+            .map(|name| name.to_owned())
+    }
+
+    fn update_config(&mut self, config: &BlindsConfig) -> Result<()> {
+        let new_config = config
+            .living_room_blinds()
+            .ok_or(error::DriverError::RoomTypeChanged)?;
+        new_config.quiet_mode.validate()?;
+        new_config.slide_profile.validate("slide_profile")?;
+        if new_config.serial_port != self.config.serial_port
+            || new_config.slide_motor_id != self.config.slide_motor_id
+            || new_config.flip_motor_id != self.config.flip_motor_id
+        {
+            warn!("Serial port and motor ID changes require a restart. Ignoring them");
+        }
+        self.config = LivingRoomBlindsConfig {
+            serial_port: self.config.serial_port.clone(),
+            slide_motor_id: self.config.slide_motor_id,
+            flip_motor_id: self.config.flip_motor_id,
+            flip_motor_left: self.config.flip_motor_left,
+            flip_motor_right: self.config.flip_motor_right,
+            slide_closed_position: self.config.slide_closed_position,
+            slide_open_position: self.config.slide_open_position,
+            ..new_config.clone()
+        };
+        Ok(())
+    }
+
+    fn set_motion_mode(&mut self, mode: Option<MotionMode>) {
+        self.mode_override = mode;
     }
 
     fn set_state_publisher(&mut self, state_publisher: StatePublisher) {
         self.state_publisher = Some(state_publisher)
     }
+
+    fn publish_state(&self) {
+        if let Some(ref state_publisher) = self.state_publisher {
+            // don't fail motion because broker is unreachable
+            if let Err(e) = state_publisher.publish(self) {
+                warn!("Failed to publish state {e}");
+            }
+        }
+    }
 }
@@ -1,44 +1,127 @@
 mod bedroom_blinds;
+mod diagnostics;
 mod living_room_blinds;
+mod motion_profile;
+mod serial;
 
+use crate::config::{BlindsConfig, MotionMode, QuietModeConfig};
 use crate::error;
+use crate::metrics;
 use crate::mqtt_server::StatePublisher;
 use anyhow::Result;
 use async_trait::async_trait;
+use log::*;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
-use std::{path::Path, time::Instant};
+use std::time::Instant;
 use tokio::time::sleep;
 
 pub use bedroom_blinds::BedroomBlinds;
+pub use blinds_protocol::BlindsState;
+pub use diagnostics::DiagnosticsCommand;
 pub use living_room_blinds::LivingRoomBlinds;
+pub use serial::find_usb_serial_port;
 
 const UNCALIBRATED_COLOR: lss_driver::LedColor = lss_driver::LedColor::Magenta;
 const CALIBRATED_COLOR: lss_driver::LedColor = lss_driver::LedColor::Off;
 
-const SLIDING_CURRENT_LIMIT: lss_driver::CommandModifier =
-    lss_driver::CommandModifier::CurrentLimp(400);
-
-const BEDROOM_LIFTING_CURRENT_LIMIT: lss_driver::CommandModifier =
-    lss_driver::CommandModifier::CurrentLimp(600);
+/// Current limits in mA
+const SLIDING_CURRENT_LIMIT: u32 = 400;
+const BEDROOM_LIFTING_CURRENT_LIMIT: u32 = 600;
 
 const SLIDING_SPEED: f32 = 340.0;
 
+/// Allowed difference between requested and reached curtain fraction
+const SLIDE_POSITION_TOLERANCE: f32 = 0.05;
+
 const LIVING_ROOM_SLIDING_TIMEOUT: Duration = Duration::from_secs(22);
 const LIVING_ROOM_FLIPPER_TIMEOUT: Duration = Duration::from_secs(3);
 const BEDROOM_SLIDING_TIMEOUT: Duration = Duration::from_secs(20);
+/// Flip motor has to be moved by hand within this time after calibration starts
+const FLIPPER_CALIBRATION_START_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time for moving flip motor between both end stops by hand
+const FLIPPER_CALIBRATION_TIME: Duration = Duration::from_secs(20);
+/// Serial queries, speed ramps and pauses between moves of a single command
+const COMMAND_TIME_MARGIN: Duration = Duration::from_secs(30);
 
 const BEDROOM_DOOR_TOP_OFFSET: f32 = 100.0;
 const BEDROOM_BLIND_BOTTOM_OFFSET: f32 = 4500.0;
 
-#[derive(Debug, serde::Serialize, Clone, Copy)]
-#[serde(rename_all = "lowercase")]
-pub enum BlindsState {
-    Open,
-    Partial,
-    Closed,
-    Opening,
-    Closing,
-    Other,
+/// Set by stop request and checked while motors move
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Stop motion of running command
+///
+/// Doesn't need the driver lock which is held by the running command
+pub fn request_stop() {
+    STOP_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Forget stop requested while no command was running
+pub fn clear_stop_request() {
+    STOP_REQUESTED.store(false, Ordering::SeqCst);
+}
+
+fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Fail with [`error::DriverError::Stopped`] once stop was requested
+fn check_stop() -> Result<()> {
+    if stop_requested() {
+        info!("Stopping on request");
+        return Err(error::DriverError::Stopped.into());
+    }
+    Ok(())
+}
+
+/// Speed, current limit and timeout scaling of selected motion mode
+#[derive(Debug, Clone, Copy)]
+struct MotionLimits {
+    speed_factor: f32,
+    current_factor: f32,
+}
+
+impl MotionLimits {
+    /// Limits for mode selected by command or by configured quiet hours
+    fn new(config: &QuietModeConfig, mode_override: Option<MotionMode>) -> Self {
+        let mode = mode_override
+            .unwrap_or_else(|| config.mode_at(chrono::Local::now().naive_local().time()));
+        match mode {
+            MotionMode::Normal => Self {
+                speed_factor: 1.0,
+                current_factor: 1.0,
+            },
+            MotionMode::Quiet => {
+                info!("Moving in quiet mode");
+                Self {
+                    speed_factor: config.speed_factor,
+                    current_factor: config.current_factor,
+                }
+            }
+        }
+    }
+
+    fn speed(&self, speed: f32) -> f32 {
+        speed * self.speed_factor
+    }
+
+    fn current_limit(&self, current_limit: u32) -> u32 {
+        (current_limit as f32 * self.current_factor) as u32
+    }
+
+    /// Slower motion needs longer to reach end stops
+    fn timeout(&self, timeout: Duration) -> Duration {
+        timeout.div_f32(self.speed_factor)
+    }
+
+    /// Slowest limits any command can run with
+    fn slowest(config: &QuietModeConfig) -> Self {
+        Self {
+            speed_factor: config.speed_factor.min(1.0),
+            current_factor: config.current_factor.min(1.0),
+        }
+    }
 }
 
 #[async_trait]
@@ -52,10 +135,65 @@ pub trait Blinds: Send {
     async fn partial_open(&mut self, open: f32) -> Result<()>;
     async fn close(&mut self) -> Result<()>;
     async fn toggle(&mut self) -> Result<()>;
+    /// Move to named preset from config
+    async fn apply_preset(&mut self, name: &str) -> Result<()>;
+    /// Move curtain without changing tilt
+    ///
+    /// # Arguments
+    ///
+    /// * `position` - number between 0.0 and 1.0 with 1.0 being full open
+    async fn set_position(&mut self, position: f32) -> Result<()>;
+    /// Tilt slats without moving curtain
+    ///
+    /// # Arguments
+    ///
+    /// * `tilt` - number between -1.0 and 1.0 with 0.0 being slats open
+    async fn set_tilt(&mut self, _tilt: f32) -> Result<()> {
+        Err(error::DriverError::TiltNotSupported.into())
+    }
+    /// Check whether motors lost power since they were calibrated
+    ///
+    /// Rebooted motors need calibration until [`Blinds::calibrate`] succeeds
     async fn were_motors_rebooted(&mut self) -> Result<bool>;
-    async fn calibrate(&mut self, config_path: &Path) -> Result<()>;
+    /// Query every motor to make sure serial bus responds
+    async fn check_motors(&mut self) -> Result<()>;
+    /// Follow USB serial adapter that was re-enumerated under another name
+    fn reopen_serial_port(&mut self) -> Result<()>;
+    /// Publish that motors can't be reached until [`Blinds::reconnect`] succeeds
+    async fn set_disconnected(&mut self) -> Result<()>;
+    /// Reopen serial port after connection was lost
+    ///
+    /// Motors that lost power meanwhile need calibration
+    async fn reconnect(&mut self) -> Result<()>;
+    /// Find end stops and save them to config file the blinds were loaded from
+    async fn calibrate(&mut self) -> Result<()>;
+    /// Calibrate only what is missing without waiting for anyone. Runs on start
+    async fn calibrate_unattended(&mut self) -> Result<()> {
+        self.calibrate().await
+    }
     fn needs_calibration(&self) -> bool;
+    /// Longest any single command including calibration can hold the driver
+    fn max_command_time(&self) -> Duration;
+    fn state(&self) -> BlindsState;
+    /// Fraction open after last finished move
+    fn position(&self) -> Option<f32>;
+    /// Slat tilt after last finished move
+    fn tilt(&self) -> Option<f32> {
+        None
+    }
+    /// Name of preset matching current position
+    fn preset(&self) -> Option<String>;
+    /// Apply reloaded configuration
+    ///
+    /// Calibration values, serial port and motor IDs are kept from the running config
+    fn update_config(&mut self, config: &BlindsConfig) -> Result<()>;
+    /// Motion mode for following commands
+    ///
+    /// `None` uses quiet mode during configured quiet hours
+    fn set_motion_mode(&mut self, mode: Option<MotionMode>);
     fn set_state_publisher(&mut self, state_publisher: StatePublisher);
+    /// Publish current state to legacy `state` and `status` topics
+    fn publish_state(&self);
 }
 
 pub async fn wait_until_motor_stopped(
@@ -66,6 +204,11 @@ pub async fn wait_until_motor_stopped(
     let start_time = Instant::now();
     sleep(Duration::from_secs(1)).await;
     loop {
+        if stop_requested() {
+            info!("Stopping motor {id} on request");
+            driver.limp(id).await?;
+            return Err(error::DriverError::Stopped.into());
+        }
         if start_time.elapsed() > timeout {
             if driver.limp(id).await.is_err() {
                 error!("Failed to stop motor after timeout");
@@ -75,9 +218,11 @@ pub async fn wait_until_motor_stopped(
                 timeout.as_millis(),
                 id
             );
+            metrics::record_wait_for_stop_timeout();
             return Err(error::DriverError::WaitingForStopTimedOut.into());
         }
         let status = driver.query_status(id).await?;
+        debug!("Motor {id} status {status:?}");
         match status {
             lss_driver::MotorStatus::Limp | lss_driver::MotorStatus::Holding => return Ok(()),
             lss_driver::MotorStatus::Unknown
@@ -85,7 +230,8 @@ pub async fn wait_until_motor_stopped(
             | lss_driver::MotorStatus::Stuck
             | lss_driver::MotorStatus::Blocked
             | lss_driver::MotorStatus::SafeMode => {
-                return Err(error::DriverError::BadMotorStatus(status).into())
+                metrics::record_bad_motor_status(&status);
+                return Err(error::DriverError::BadMotorStatus(status).into());
             }
             _ => (),
         }
@@ -0,0 +1,397 @@
+use crate::{config::MotionProfile, error, metrics};
+use anyhow::Result;
+use async_trait::async_trait;
+use log::*;
+use lss_driver::MotorStatus;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// How often speed is updated while following a plan
+pub const CONTROL_PERIOD: Duration = Duration::from_millis(50);
+
+/// Motor that can be driven at given speed
+#[async_trait]
+pub trait SpeedControl: Send {
+    /// Rotate at given speed in degrees per second
+    async fn set_speed(&mut self, speed: f32) -> Result<()>;
+
+    async fn query_status(&mut self) -> Result<MotorStatus>;
+}
+
+/// LSS motor rotating with current limit
+pub struct LssSpeedControl<'a> {
+    driver: &'a mut lss_driver::LSSDriver,
+    id: u8,
+    current_limit: u32,
+}
+
+impl<'a> LssSpeedControl<'a> {
+    pub fn new(driver: &'a mut lss_driver::LSSDriver, id: u8, current_limit: u32) -> Self {
+        Self {
+            driver,
+            id,
+            current_limit,
+        }
+    }
+}
+
+#[async_trait]
+impl SpeedControl for LssSpeedControl<'_> {
+    async fn set_speed(&mut self, speed: f32) -> Result<()> {
+        self.driver
+            .set_rotation_speed_with_modifier(
+                self.id,
+                speed,
+                lss_driver::CommandModifier::CurrentLimp(self.current_limit),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn query_status(&mut self) -> Result<MotorStatus> {
+        Ok(self.driver.query_status(self.id).await?)
+    }
+}
+
+/// Speed over time for a single move
+///
+/// Speed ramps up to cruise speed, cruises and ramps down to stop after given distance
+#[derive(Debug, Clone)]
+pub struct MotionPlan {
+    /// 1.0 or -1.0
+    direction: f32,
+    /// S-curve ramps follow smoothstep instead of straight line
+    smooth: bool,
+    cruise_speed: f32,
+    ramp_time: f32,
+    cruise_time: f32,
+}
+
+impl MotionPlan {
+    /// Plan move over signed distance in degrees
+    ///
+    /// Fails on acceleration that isn't a positive number
+    pub fn new(profile: &MotionProfile, max_speed: f32, distance: f32) -> Result<Self> {
+        let direction = if distance < 0.0 { -1.0 } else { 1.0 };
+        let distance = distance.abs();
+        let (acceleration, smooth) = match *profile {
+            MotionProfile::Constant => {
+                return Ok(Self {
+                    direction,
+                    smooth: false,
+                    cruise_speed: max_speed,
+                    ramp_time: 0.0,
+                    cruise_time: distance / max_speed,
+                })
+            }
+            MotionProfile::Trapezoidal { acceleration } => (acceleration, false),
+            MotionProfile::SCurve { acceleration } => (acceleration, true),
+        };
+        profile.validate("profile")?;
+        // smoothstep peaks at 1.5 times average acceleration
+        let ramp_factor = if smooth { 1.5 } else { 1.0 };
+        // ramp up and ramp down together take ramp_factor * speed^2 / acceleration
+        let cruise_speed = if distance >= ramp_factor * max_speed * max_speed / acceleration {
+            max_speed
+        } else {
+            (distance * acceleration / ramp_factor).sqrt()
+        };
+        let ramp_time = ramp_factor * cruise_speed / acceleration;
+        let cruise_time = if cruise_speed > 0.0 {
+            (distance - cruise_speed * ramp_time).max(0.0) / cruise_speed
+        } else {
+            0.0
+        };
+        Ok(Self {
+            direction,
+            smooth,
+            cruise_speed,
+            ramp_time,
+            cruise_time,
+        })
+    }
+
+    /// Plan move that runs at signed speed until something stops the motor
+    pub fn unbounded(profile: &MotionProfile, speed: f32) -> Result<Self> {
+        Self::new(profile, speed.abs(), f32::INFINITY.copysign(speed))
+    }
+
+    /// Seconds until the plan stops
+    pub fn duration(&self) -> f32 {
+        2.0 * self.ramp_time + self.cruise_time
+    }
+
+    /// Signed cruise speed in degrees per second
+    pub fn cruise_speed(&self) -> f32 {
+        self.direction * self.cruise_speed
+    }
+
+    /// Signed speed in degrees per second at given second of the plan
+    pub fn speed_at(&self, time: f32) -> f32 {
+        let duration = self.duration();
+        let speed = if time < 0.0 || time >= duration {
+            0.0
+        } else if time < self.ramp_time {
+            self.cruise_speed * self.ramp(time / self.ramp_time)
+        } else if time < self.ramp_time + self.cruise_time {
+            self.cruise_speed
+        } else {
+            self.cruise_speed * self.ramp((duration - time) / self.ramp_time)
+        };
+        self.direction * speed
+    }
+
+    fn ramp(&self, progress: f32) -> f32 {
+        if self.smooth {
+            progress * progress * (3.0 - 2.0 * progress)
+        } else {
+            progress
+        }
+    }
+}
+
+/// Motor stopped itself and must not be commanded again
+fn is_fault(status: &MotorStatus) -> bool {
+    matches!(
+        status,
+        MotorStatus::Stuck | MotorStatus::Blocked | MotorStatus::SafeMode
+    )
+}
+
+fn bad_status(status: MotorStatus) -> anyhow::Error {
+    metrics::record_bad_motor_status(&status);
+    error::DriverError::BadMotorStatus(status).into()
+}
+
+/// Command speeds along the whole plan and stop
+///
+/// Motor going limp on current limit before the plan ends is an error.
+/// New speed commands would power it again
+pub async fn follow_plan(motor: &mut impl SpeedControl, plan: &MotionPlan) -> Result<()> {
+    let period = CONTROL_PERIOD.as_secs_f32();
+    let mut time = 0.0;
+    while time < plan.duration() {
+        if super::stop_requested() {
+            motor.set_speed(0.0).await?;
+            return Err(error::DriverError::Stopped.into());
+        }
+        // middle of the period keeps travelled distance close to plan
+        motor.set_speed(plan.speed_at(time + period / 2.0)).await?;
+        sleep(CONTROL_PERIOD).await;
+        time += period;
+        let status = motor.query_status().await?;
+        if matches!(status, MotorStatus::Limp) || is_fault(&status) {
+            error!("Motor stopped with {status:?} while following plan");
+            return Err(bad_status(status));
+        }
+    }
+    motor.set_speed(0.0).await?;
+    Ok(())
+}
+
+/// Command speeds along ramp up and leave motor running at cruise speed
+pub async fn ramp_up(motor: &mut impl SpeedControl, plan: &MotionPlan) -> Result<()> {
+    let period = CONTROL_PERIOD.as_secs_f32();
+    let mut time = 0.0;
+    while time < plan.ramp_time {
+        if super::stop_requested() {
+            motor.set_speed(0.0).await?;
+            return Err(error::DriverError::Stopped.into());
+        }
+        motor.set_speed(plan.speed_at(time + period / 2.0)).await?;
+        sleep(CONTROL_PERIOD).await;
+        time += period;
+        let status = motor.query_status().await?;
+        if matches!(status, MotorStatus::Limp) {
+            // moves that run until stopped end on current limit
+            info!("Motor went limp while ramping up");
+            return Ok(());
+        }
+        if is_fault(&status) {
+            error!("Motor stopped with {status:?} while ramping up");
+            return Err(bad_status(status));
+        }
+    }
+    motor.set_speed(plan.cruise_speed()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MAX_SPEED: f32 = 340.0;
+    const ACCELERATION: f32 = 400.0;
+
+    /// Integrates commanded speed over control period
+    #[derive(Default)]
+    struct SimulatedMotor {
+        speeds: Vec<f32>,
+        position: f32,
+        /// Trip current limit after this many speed commands
+        limp_after: Option<usize>,
+    }
+
+    #[async_trait]
+    impl SpeedControl for SimulatedMotor {
+        async fn set_speed(&mut self, speed: f32) -> Result<()> {
+            self.speeds.push(speed);
+            self.position += speed * CONTROL_PERIOD.as_secs_f32();
+            Ok(())
+        }
+
+        async fn query_status(&mut self) -> Result<MotorStatus> {
+            match self.limp_after {
+                Some(limit) if self.speeds.len() >= limit => Ok(MotorStatus::Limp),
+                _ => Ok(MotorStatus::Traveling),
+            }
+        }
+    }
+
+    async fn simulate(profile: MotionProfile, distance: f32) -> SimulatedMotor {
+        let plan = MotionPlan::new(&profile, MAX_SPEED, distance).unwrap();
+        let mut motor = SimulatedMotor::default();
+        follow_plan(&mut motor, &plan).await.unwrap();
+        motor
+    }
+
+    fn max_speed_change(motor: &SimulatedMotor) -> f32 {
+        motor
+            .speeds
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).abs())
+            .fold(0.0, f32::max)
+    }
+
+    fn assert_within_limits(motor: &SimulatedMotor, distance: f32) {
+        let period = CONTROL_PERIOD.as_secs_f32();
+        assert!(motor.speeds.iter().all(|speed| speed.abs() <= MAX_SPEED));
+        assert!(max_speed_change(motor) <= ACCELERATION * period * 1.001);
+        assert_eq!(motor.speeds.last(), Some(&0.0));
+        // last partial period can overshoot by a little
+        assert!((motor.position - distance).abs() < MAX_SPEED * period);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn trapezoidal_respects_limits() {
+        let profile = MotionProfile::Trapezoidal {
+            acceleration: ACCELERATION,
+        };
+        let motor = simulate(profile, 2000.0).await;
+        assert_within_limits(&motor, 2000.0);
+        assert!(motor.speeds.contains(&MAX_SPEED));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn s_curve_respects_limits() {
+        let profile = MotionProfile::SCurve {
+            acceleration: ACCELERATION,
+        };
+        let motor = simulate(profile, 2000.0).await;
+        assert_within_limits(&motor, 2000.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn s_curve_starts_softer_than_trapezoidal() {
+        let trapezoidal = simulate(
+            MotionProfile::Trapezoidal {
+                acceleration: ACCELERATION,
+            },
+            2000.0,
+        )
+        .await;
+        let s_curve = simulate(
+            MotionProfile::SCurve {
+                acceleration: ACCELERATION,
+            },
+            2000.0,
+        )
+        .await;
+        assert!(s_curve.speeds[0] < trapezoidal.speeds[0]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn short_move_does_not_reach_max_speed() {
+        let profile = MotionProfile::Trapezoidal {
+            acceleration: ACCELERATION,
+        };
+        let motor = simulate(profile, 100.0).await;
+        assert_within_limits(&motor, 100.0);
+        assert!(motor.speeds.iter().all(|speed| *speed < MAX_SPEED));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reverse_move_uses_negative_speeds() {
+        let profile = MotionProfile::SCurve {
+            acceleration: ACCELERATION,
+        };
+        let motor = simulate(profile, -1500.0).await;
+        assert_within_limits(&motor, -1500.0);
+        assert!(motor.speeds.iter().all(|speed| *speed <= 0.0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ramp_up_ends_at_cruise_speed() {
+        let profile = MotionProfile::Trapezoidal {
+            acceleration: ACCELERATION,
+        };
+        let plan = MotionPlan::unbounded(&profile, -MAX_SPEED).unwrap();
+        let mut motor = SimulatedMotor::default();
+        ramp_up(&mut motor, &plan).await.unwrap();
+        assert_eq!(motor.speeds.last(), Some(&-MAX_SPEED));
+        assert!(max_speed_change(&motor) <= ACCELERATION * CONTROL_PERIOD.as_secs_f32() * 1.001);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn follow_plan_fails_when_motor_goes_limp() {
+        let profile = MotionProfile::Trapezoidal {
+            acceleration: ACCELERATION,
+        };
+        let plan = MotionPlan::new(&profile, MAX_SPEED, 2000.0).unwrap();
+        let mut motor = SimulatedMotor {
+            limp_after: Some(10),
+            ..Default::default()
+        };
+        let error = follow_plan(&mut motor, &plan).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<error::DriverError>(),
+            Some(error::DriverError::BadMotorStatus(MotorStatus::Limp))
+        ));
+        // limp motor is not powered again
+        assert_eq!(motor.speeds.len(), 10);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ramp_up_stops_when_motor_goes_limp() {
+        let profile = MotionProfile::Trapezoidal {
+            acceleration: ACCELERATION,
+        };
+        let plan = MotionPlan::unbounded(&profile, MAX_SPEED).unwrap();
+        let mut motor = SimulatedMotor {
+            limp_after: Some(3),
+            ..Default::default()
+        };
+        ramp_up(&mut motor, &plan).await.unwrap();
+        assert_eq!(motor.speeds.len(), 3);
+        assert!(motor.speeds.iter().all(|speed| *speed < MAX_SPEED));
+    }
+
+    #[test]
+    fn constant_profile_runs_at_max_speed() {
+        let plan = MotionPlan::new(&MotionProfile::Constant, MAX_SPEED, 680.0).unwrap();
+        assert_eq!(plan.duration(), 2.0);
+        assert_eq!(plan.speed_at(0.0), MAX_SPEED);
+        assert_eq!(plan.speed_at(1.99), MAX_SPEED);
+        assert_eq!(plan.speed_at(2.0), 0.0);
+    }
+
+    #[test]
+    fn acceleration_has_to_be_positive_number() {
+        for acceleration in [0.0, -400.0, f32::NAN, f32::INFINITY] {
+            let trapezoidal = MotionProfile::Trapezoidal { acceleration };
+            let s_curve = MotionProfile::SCurve { acceleration };
+            assert!(MotionPlan::new(&trapezoidal, MAX_SPEED, 2000.0).is_err());
+            assert!(MotionPlan::unbounded(&s_curve, MAX_SPEED).is_err());
+        }
+    }
+}
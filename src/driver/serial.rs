@@ -0,0 +1,172 @@
+use crate::config::{SerialPortConfig, UsbSerialMatcher};
+use anyhow::Result;
+use log::*;
+use std::{
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+};
+
+const SYSFS_ROOT: &str = "/sys";
+
+/// Find tty of USB serial adapter by scanning sysfs
+pub fn find_usb_serial_port(matcher: &UsbSerialMatcher) -> Result<PathBuf> {
+    find_usb_serial_port_in(Path::new(SYSFS_ROOT), matcher)
+}
+
+fn find_usb_serial_port_in(sysfs_root: &Path, matcher: &UsbSerialMatcher) -> Result<PathBuf> {
+    let mut found = vec![];
+    for entry in std::fs::read_dir(sysfs_root.join("class/tty"))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // tty devices without a bus like ttyS0 have no device link
+        let device = match std::fs::canonicalize(entry.path().join("device")) {
+            Ok(device) => device,
+            Err(_) => continue,
+        };
+        // USB attributes are on the device a few levels above the interface
+        let usb_device = match device
+            .ancestors()
+            .find(|path| path.join("idVendor").exists())
+        {
+            Some(usb_device) => usb_device,
+            None => continue,
+        };
+        if matcher.matches(
+            read_attribute(usb_device, "idVendor").as_deref(),
+            read_attribute(usb_device, "idProduct").as_deref(),
+            read_attribute(usb_device, "serial").as_deref(),
+        ) {
+            found.push(Path::new("/dev").join(name));
+        }
+    }
+    found.sort();
+    match found.len() {
+        0 => anyhow::bail!("no serial adapter matching {matcher}"),
+        1 => Ok(found.remove(0)),
+        _ => anyhow::bail!(
+            "{} serial adapters match {matcher}: {:?}. Add serial_number to pick one",
+            found.len(),
+            found
+        ),
+    }
+}
+
+fn read_attribute(device: &Path, attribute: &str) -> Option<String> {
+    std::fs::read_to_string(device.join(attribute))
+        .ok()
+        .map(|value| value.trim().to_owned())
+}
+
+/// Serial driver that follows adapter to a new tty after re-enumeration
+pub struct SerialConnection {
+    config: SerialPortConfig,
+    path: PathBuf,
+    driver: lss_driver::LSSDriver,
+}
+
+impl SerialConnection {
+    pub fn open(config: &SerialPortConfig) -> Result<Self> {
+        let path = config.resolve()?;
+        info!("Opening serial port {}", path.display());
+        let driver = lss_driver::LSSDriver::new(&path.to_string_lossy())?;
+        Ok(Self {
+            config: config.clone(),
+            path,
+            driver,
+        })
+    }
+
+    /// Reopen port if adapter now shows up under another name
+    ///
+    /// Plain paths never move
+    pub fn reopen_if_moved(&mut self) -> Result<()> {
+        let path = self.config.resolve()?;
+        if path != self.path {
+            warn!(
+                "Serial adapter moved from {} to {}. Reopening",
+                self.path.display(),
+                path.display()
+            );
+            self.reopen()?;
+        }
+        Ok(())
+    }
+
+    /// Close port and open it again at its current path
+    pub fn reopen(&mut self) -> Result<()> {
+        *self = Self::open(&self.config)?;
+        Ok(())
+    }
+}
+
+impl Deref for SerialConnection {
+    type Target = lss_driver::LSSDriver;
+
+    fn deref(&self) -> &Self::Target {
+        &self.driver
+    }
+}
+
+impl DerefMut for SerialConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.driver
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+
+    /// Fake sysfs with CH340 on ttyUSB1 and FTDI on ttyUSB0
+    fn fake_sysfs() -> PathBuf {
+        let root = std::env::temp_dir().join(format!("blinds_sysfs_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for (tty, usb_device, vendor, product, serial) in [
+            ("ttyUSB0", "1-1", "0403", "6001", "A50285BI"),
+            ("ttyUSB1", "1-2", "1a86", "7523", "0001"),
+        ] {
+            let device = root.join(format!("devices/usb1/{usb_device}"));
+            let interface = device.join(format!("{usb_device}:1.0/{tty}"));
+            std::fs::create_dir_all(&interface).unwrap();
+            std::fs::write(device.join("idVendor"), format!("{vendor}\n")).unwrap();
+            std::fs::write(device.join("idProduct"), format!("{product}\n")).unwrap();
+            std::fs::write(device.join("serial"), format!("{serial}\n")).unwrap();
+            let class_dir = root.join("class/tty").join(tty);
+            std::fs::create_dir_all(&class_dir).unwrap();
+            std::os::unix::fs::symlink(&interface, class_dir.join("device")).unwrap();
+        }
+        // serial port without USB device
+        std::fs::create_dir_all(root.join("class/tty/ttyS0")).unwrap();
+        root
+    }
+
+    #[test]
+    fn finds_adapter_by_ids_and_serial() {
+        let root = fake_sysfs();
+        let by_ids = UsbSerialMatcher {
+            vendor_id: Some("1A86".to_owned()),
+            product_id: Some("7523".to_owned()),
+            serial_number: None,
+        };
+        assert_eq!(
+            find_usb_serial_port_in(&root, &by_ids).unwrap(),
+            Path::new("/dev/ttyUSB1")
+        );
+        let by_serial = UsbSerialMatcher {
+            vendor_id: None,
+            product_id: None,
+            serial_number: Some("A50285BI".to_owned()),
+        };
+        assert_eq!(
+            find_usb_serial_port_in(&root, &by_serial).unwrap(),
+            Path::new("/dev/ttyUSB0")
+        );
+        let missing = UsbSerialMatcher {
+            vendor_id: Some("10c4".to_owned()),
+            product_id: None,
+            serial_number: None,
+        };
+        assert!(find_usb_serial_port_in(&root, &missing).is_err());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -13,6 +13,50 @@ pub enum DriverError {
     BothRoomConfigsPresent,
     #[error("waiting for stop timed out")]
     WaitingForStopTimedOut,
+    #[error("configured room type does not match running driver")]
+    RoomTypeChanged,
     #[error("partial position out of range")]
     PartialPositionOutOfRange,
+    #[error("unknown preset {0}")]
+    UnknownPreset(String),
+    #[error("slats have to be open to slide curtain")]
+    SlatsNotOpen,
+    #[error("curtain has to be closed to tilt slats")]
+    CurtainNotClosed,
+    #[error("tilt is not supported by these blinds")]
+    TiltNotSupported,
+    #[error("slide stopped at {actual:.2} instead of {expected:.2}")]
+    SlidePositionNotReached { expected: f32, actual: f32 },
+    #[error("serial connection to motors lost")]
+    Disconnected,
+    #[error("motion stopped by request")]
+    Stopped,
+    #[error("flip motor wasn't moved to start calibration")]
+    CalibrationNotStarted,
+    #[error("flip motor has to be calibrated by hand with calibrate command")]
+    ManualCalibrationNeeded,
+}
+
+impl DriverError {
+    /// Variant name reported to MQTT clients
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DriverError::BadMotorStatus(_) => "BadMotorStatus",
+            DriverError::MissingMotorConfig => "MissingMotorConfig",
+            DriverError::MissingRoomConfiguration => "MissingRoomConfiguration",
+            DriverError::BothRoomConfigsPresent => "BothRoomConfigsPresent",
+            DriverError::WaitingForStopTimedOut => "WaitingForStopTimedOut",
+            DriverError::RoomTypeChanged => "RoomTypeChanged",
+            DriverError::PartialPositionOutOfRange => "PartialPositionOutOfRange",
+            DriverError::UnknownPreset(_) => "UnknownPreset",
+            DriverError::SlatsNotOpen => "SlatsNotOpen",
+            DriverError::CurtainNotClosed => "CurtainNotClosed",
+            DriverError::TiltNotSupported => "TiltNotSupported",
+            DriverError::SlidePositionNotReached { .. } => "SlidePositionNotReached",
+            DriverError::Disconnected => "Disconnected",
+            DriverError::Stopped => "Stopped",
+            DriverError::CalibrationNotStarted => "CalibrationNotStarted",
+            DriverError::ManualCalibrationNeeded => "ManualCalibrationNeeded",
+        }
+    }
 }
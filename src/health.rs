@@ -0,0 +1,225 @@
+use crate::{
+    driver::{Blinds, BlindsState},
+    history::MotionHistory,
+    mqtt_server::MqttConnectionStats,
+    supervisor::ConnectionSupervisor,
+};
+use anyhow::Result;
+use log::*;
+use serde::Serialize;
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::Mutex, time::timeout};
+
+/// Serial bus that doesn't answer in this time is considered hung
+const MOTOR_QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Reconnecting for longer than this probably needs a restart
+const MAX_DISCONNECTED_TIME: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MotorCheck {
+    Ok,
+    /// Command is running so motors weren't queried
+    Busy,
+    /// Serial port is being reopened
+    Disconnected,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub motors: MotorCheck,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub motor_error: Option<String>,
+    pub mqtt_connected: bool,
+    /// Unknown while a command is running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub calibrated: Option<bool>,
+}
+
+/// Checks used by `/readyz` and systemd watchdog
+pub struct HealthChecker {
+    blinds: Arc<Mutex<Box<dyn Blinds>>>,
+    mqtt_stats: Arc<MqttConnectionStats>,
+    history: Arc<MotionHistory>,
+    supervisor: Arc<ConnectionSupervisor>,
+}
+
+impl HealthChecker {
+    pub fn new(
+        blinds: Arc<Mutex<Box<dyn Blinds>>>,
+        mqtt_stats: Arc<MqttConnectionStats>,
+        history: Arc<MotionHistory>,
+        supervisor: Arc<ConnectionSupervisor>,
+    ) -> Self {
+        Self {
+            blinds,
+            mqtt_stats,
+            history,
+            supervisor,
+        }
+    }
+
+    /// Query motors unless a command is running
+    ///
+    /// Running command counts as healthy until it holds the driver for longer than the driver
+    /// says any command can take. Lost connection counts as healthy for a while too because
+    /// supervisor is already reconnecting
+    pub async fn check_motors(&self) -> Result<(MotorCheck, Option<bool>)> {
+        let mut blinds = match self.blinds.try_lock() {
+            Ok(blinds) => blinds,
+            Err(_) => {
+                if let Some(command) = self.history.running_command() {
+                    let running_time = command.started.elapsed();
+                    if running_time > command.max_time {
+                        anyhow::bail!("command running for {}s", running_time.as_secs());
+                    }
+                }
+                return Ok((MotorCheck::Busy, None));
+            }
+        };
+        if blinds.state() == BlindsState::Disconnected {
+            if let Some(disconnected_for) = self.supervisor.disconnected_for() {
+                if disconnected_for > MAX_DISCONNECTED_TIME {
+                    anyhow::bail!("motors disconnected for {}s", disconnected_for.as_secs());
+                }
+            }
+            return Ok((MotorCheck::Disconnected, None));
+        }
+        let result = timeout(MOTOR_QUERY_TIMEOUT, blinds.check_motors())
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("motors didn't answer in time")));
+        if result.is_err() {
+            self.supervisor.connection_lost();
+        }
+        result?;
+        Ok((MotorCheck::Ok, Some(!blinds.needs_calibration())))
+    }
+
+    pub async fn readiness(&self) -> Readiness {
+        let mqtt_connected = self.mqtt_stats.is_connected();
+        let (motors, motor_error, calibrated) = match self.check_motors().await {
+            Ok((motors, calibrated)) => (motors, None, calibrated),
+            Err(e) => {
+                warn!("Motor check failed {e}");
+                (MotorCheck::Failed, Some(e.to_string()), None)
+            }
+        };
+        Readiness {
+            ready: matches!(motors, MotorCheck::Ok | MotorCheck::Busy)
+                && mqtt_connected
+                && calibrated != Some(false),
+            motors,
+            motor_error,
+            mqtt_connected,
+            calibrated,
+        }
+    }
+}
+
+/// systemd readiness notification and watchdog
+///
+/// Protocol is described in `sd_notify(3)`
+#[cfg(unix)]
+pub mod systemd {
+    use super::HealthChecker;
+    use log::*;
+    use std::{os::unix::net::UnixDatagram, sync::Arc, time::Duration};
+
+    fn notify(state: &str) -> std::io::Result<()> {
+        let socket_path = match std::env::var_os("NOTIFY_SOCKET") {
+            Some(socket_path) => socket_path,
+            // not started by systemd
+            None => return Ok(()),
+        };
+        let socket = UnixDatagram::unbound()?;
+        match socket_path.to_str().and_then(|path| path.strip_prefix('@')) {
+            #[cfg(target_os = "linux")]
+            Some(abstract_name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let address =
+                    std::os::unix::net::SocketAddr::from_abstract_name(abstract_name.as_bytes())?;
+                socket.send_to_addr(state.as_bytes(), &address)?;
+            }
+            _ => {
+                socket.send_to(state.as_bytes(), &socket_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn notify_ready() {
+        if let Err(e) = notify("READY=1") {
+            error!("Failed to notify systemd {e}");
+        }
+    }
+
+    /// Interval requested by `WatchdogSec` of the unit
+    fn watchdog_interval() -> Option<Duration> {
+        if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+            if pid.parse() != Ok(std::process::id()) {
+                return None;
+            }
+        }
+        let micros = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+        Some(Duration::from_micros(micros))
+    }
+
+    /// Ping watchdog for as long as motors answer or serial port is being reopened
+    ///
+    /// Missed pings make systemd restart the service. MQTT is not checked
+    /// because restarting doesn't help when the broker is down
+    pub fn start_watchdog(checker: Arc<HealthChecker>) {
+        let interval = match watchdog_interval() {
+            Some(interval) => interval,
+            None => return,
+        };
+        info!(
+            "Pinging systemd watchdog every {}ms",
+            (interval / 2).as_millis()
+        );
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval / 2).await;
+                match checker.check_motors().await {
+                    Ok(_) => {
+                        if let Err(e) = notify("WATCHDOG=1") {
+                            error!("Failed to ping systemd watchdog {e}");
+                        }
+                    }
+                    Err(e) => error!("Skipping watchdog ping. Motor check failed {e}"),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::routes::test::{fake_blinds_on_bus, FakeBus};
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test(start_paused = true)]
+    async fn long_disconnect_fails_check() {
+        let bus = Arc::new(FakeBus::default());
+        let (blinds, _) = fake_blinds_on_bus(bus.clone());
+        let supervisor = ConnectionSupervisor::start(blinds.clone());
+        let checker = HealthChecker::new(
+            blinds,
+            Default::default(),
+            Arc::new(MotionHistory::new(None, 10).unwrap()),
+            supervisor.clone(),
+        );
+
+        bus.disconnected.store(true, Ordering::SeqCst);
+        supervisor.connection_lost();
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        let (motors, _) = checker.check_motors().await.unwrap();
+        assert_eq!(motors, MotorCheck::Disconnected);
+
+        tokio::time::sleep(MAX_DISCONNECTED_TIME).await;
+        assert!(checker.check_motors().await.is_err());
+    }
+}
@@ -0,0 +1,570 @@
+use crate::{
+    driver::{self, Blinds, BlindsState},
+    error::DriverError,
+    metrics::{CommandSource, CommandTimer},
+    mqtt_server::{EventPublisher, StatePublisher},
+    routes::{self, BlindsAction, CommandError},
+    status::StatusModel,
+    supervisor::ConnectionSupervisor,
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// Where the blinds were before or after a motion
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PositionSnapshot {
+    pub state: BlindsState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tilt: Option<f32>,
+}
+
+impl PositionSnapshot {
+    fn of(blinds: &dyn Blinds) -> Self {
+        Self {
+            state: blinds.state(),
+            position: blinds.position(),
+            tilt: blinds.tilt(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Error,
+}
+
+/// Single finished motion
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MotionRecord {
+    pub start: DateTime<Utc>,
+    pub source: CommandSource,
+    pub action: BlindsAction,
+    pub before: PositionSnapshot,
+    pub after: PositionSnapshot,
+    pub duration_seconds: f32,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<CommandError>,
+}
+
+/// Query of `GET /history`
+///
+/// All set fields have to match. `limit` keeps the newest records
+#[derive(Debug, Default, Deserialize)]
+pub struct HistoryFilter {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub source: Option<CommandSource>,
+    /// Action name such as `open` or `preset`
+    pub action: Option<String>,
+    pub outcome: Option<Outcome>,
+    pub limit: Option<usize>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, record: &MotionRecord) -> bool {
+        self.since
+            .map(|since| record.start >= since)
+            .unwrap_or(true)
+            && self
+                .until
+                .map(|until| record.start <= until)
+                .unwrap_or(true)
+            && self
+                .source
+                .map(|source| record.source == source)
+                .unwrap_or(true)
+            && self
+                .action
+                .as_ref()
+                .map(|action| record.action.name() == action)
+                .unwrap_or(true)
+            && self
+                .outcome
+                .map(|outcome| record.outcome == outcome)
+                .unwrap_or(true)
+    }
+}
+
+/// Appends records to history file on its own thread
+///
+/// File writes and `sync_all` block so they are kept off the async runtime
+struct HistoryWriter {
+    /// Sender is only `Sync` on recent toolchains
+    sender: Mutex<Option<mpsc::Sender<MotionRecord>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HistoryWriter {
+    fn start(
+        path: PathBuf,
+        capacity: usize,
+        mut records: VecDeque<MotionRecord>,
+        mut file_lines: usize,
+    ) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel::<MotionRecord>();
+        let thread = std::thread::Builder::new()
+            .name("history-writer".to_owned())
+            .spawn(move || {
+                for record in receiver {
+                    records.push_back(record);
+                    if records.len() > capacity {
+                        records.pop_front();
+                    }
+                    if let Err(e) = persist(&path, capacity, &records, &mut file_lines) {
+                        warn!("Failed to write motion history {e}");
+                    }
+                }
+            })?;
+        Ok(Self {
+            sender: Mutex::new(Some(sender)),
+            thread: Some(thread),
+        })
+    }
+
+    fn write(&self, record: MotionRecord) {
+        if let Some(ref sender) = *self.sender.lock().unwrap() {
+            if sender.send(record).is_err() {
+                warn!("Motion history writer stopped");
+            }
+        }
+    }
+}
+
+impl Drop for HistoryWriter {
+    /// Finish writing queued records
+    fn drop(&mut self) {
+        self.sender.lock().unwrap().take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Motion history writer panicked");
+            }
+        }
+    }
+}
+
+/// Append newest record or rewrite file once it holds twice the capacity
+///
+/// `file_lines` counts lines in history file including ones already dropped from `records`
+fn persist(
+    path: &Path,
+    capacity: usize,
+    records: &VecDeque<MotionRecord>,
+    file_lines: &mut usize,
+) -> Result<()> {
+    if *file_lines + 1 >= 2 * capacity {
+        // write whole buffer to new file so that history is never half written
+        let temp_path = path.with_extension("jsonl.tmp");
+        let mut file = File::create(&temp_path)?;
+        for record in records {
+            writeln!(file, "{}", serde_json::to_string(record)?)?;
+        }
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+        *file_lines = records.len();
+    } else if let Some(record) = records.back() {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+        *file_lines += 1;
+    }
+    Ok(())
+}
+
+/// Read records from history file
+///
+/// Lines that can't be parsed are skipped. Returns newest records and number of lines in file
+fn load(path: &Path, capacity: usize) -> (VecDeque<MotionRecord>, usize) {
+    let mut records = VecDeque::with_capacity(capacity);
+    let mut file_lines = 0;
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Failed to open history {} {e}", path.display());
+            return (records, file_lines);
+        }
+    };
+    // split instead of lines so that invalid UTF-8 only skips one line
+    for line in BufReader::new(file).split(b'\n') {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Stopped reading history after line {file_lines} {e}");
+                break;
+            }
+        };
+        file_lines += 1;
+        match serde_json::from_slice(&line) {
+            Ok(record) => records.push_back(record),
+            Err(e) => warn!("Skipping bad history line {file_lines} {e}"),
+        }
+        if records.len() > capacity {
+            records.pop_front();
+        }
+    }
+    info!(
+        "Loaded {} motions from history {}",
+        records.len(),
+        path.display()
+    );
+    (records, file_lines)
+}
+
+/// Command currently holding the driver
+#[derive(Debug, Clone, Copy)]
+pub struct RunningCommand {
+    /// When the command took the driver lock
+    pub started: Instant,
+    /// Longest the driver says a command can take
+    pub max_time: Duration,
+}
+
+/// Ring buffer of recent motions persisted as JSON lines
+///
+/// File is appended on every motion and rewritten once it holds twice the capacity
+pub struct MotionHistory {
+    capacity: usize,
+    records: Mutex<VecDeque<MotionRecord>>,
+    /// `None` keeps history only in memory
+    writer: Option<HistoryWriter>,
+    running: Mutex<Option<RunningCommand>>,
+    event_publisher: Mutex<Option<EventPublisher>>,
+    state_publisher: Mutex<Option<StatePublisher>>,
+    supervisor: Mutex<Option<Arc<ConnectionSupervisor>>>,
+    status: Arc<StatusModel>,
+}
+
+impl MotionHistory {
+    /// Load history from file if it exists. `None` keeps history only in memory
+    pub fn new(file: Option<PathBuf>, capacity: usize) -> Result<Self> {
+        let (records, writer) = match file {
+            Some(path) => {
+                let (records, file_lines) = if path.exists() {
+                    load(&path, capacity)
+                } else {
+                    (VecDeque::with_capacity(capacity), 0)
+                };
+                let writer = HistoryWriter::start(path, capacity, records.clone(), file_lines)?;
+                (records, Some(writer))
+            }
+            None => (VecDeque::with_capacity(capacity), None),
+        };
+        Ok(Self {
+            capacity,
+            records: Mutex::new(records),
+            writer,
+            running: Mutex::new(None),
+            event_publisher: Mutex::new(None),
+            state_publisher: Mutex::new(None),
+            supervisor: Mutex::new(None),
+            status: Default::default(),
+        })
+    }
+
+    pub fn set_event_publisher(&self, event_publisher: EventPublisher) {
+        *self.event_publisher.lock().unwrap() = Some(event_publisher);
+    }
+
+    /// Publishes outcome of commands to status topic
+    pub fn set_state_publisher(&self, state_publisher: StatePublisher) {
+        *self.state_publisher.lock().unwrap() = Some(state_publisher);
+    }
+
+    /// Last command as seen by status topic and `GET /state`
+    pub fn status_model(&self) -> Arc<StatusModel> {
+        self.status.clone()
+    }
+
+    pub fn set_supervisor(&self, supervisor: Arc<ConnectionSupervisor>) {
+        *self.supervisor.lock().unwrap() = Some(supervisor);
+    }
+
+    /// Command run by [`MotionHistory::run`] right now
+    pub fn running_command(&self) -> Option<RunningCommand> {
+        *self.running.lock().unwrap()
+    }
+
+    /// Run action and record it in history and metrics
+    ///
+    /// Serial errors are reported to supervisor. Actions fail right away while disconnected
+    pub async fn run(
+        &self,
+        source: CommandSource,
+        action: &BlindsAction,
+        blinds: &mut dyn Blinds,
+    ) -> Result<()> {
+        let timer = CommandTimer::start(source, action.name());
+        let start = Utc::now();
+        let start_instant = Instant::now();
+        // callers hold the driver lock for the whole run
+        *self.running.lock().unwrap() = Some(RunningCommand {
+            started: start_instant,
+            max_time: blinds.max_command_time(),
+        });
+        let before = PositionSnapshot::of(blinds);
+        self.status.command_started(source, action);
+        driver::clear_stop_request();
+        let result = if before.state == BlindsState::Disconnected {
+            Err(DriverError::Disconnected.into())
+        } else {
+            routes::run_action(action, blinds).await
+        };
+        if let Err(ref e) = result {
+            if is_serial_error(e) {
+                if let Some(ref supervisor) = *self.supervisor.lock().unwrap() {
+                    supervisor.connection_lost();
+                }
+            }
+        }
+        *self.running.lock().unwrap() = None;
+        timer.finish(&result);
+        // driver already published where the blinds ended up
+        if let Some(status) = self.status.command_finished(&result) {
+            if let Some(ref state_publisher) = *self.state_publisher.lock().unwrap() {
+                if let Err(e) = state_publisher.publish_status(&status) {
+                    warn!("Failed to publish status {e}");
+                }
+            }
+        }
+        self.record(MotionRecord {
+            start,
+            source,
+            action: action.clone(),
+            before,
+            after: PositionSnapshot::of(blinds),
+            duration_seconds: start_instant.elapsed().as_secs_f32(),
+            outcome: match result {
+                Ok(()) => Outcome::Success,
+                Err(_) => Outcome::Error,
+            },
+            error: result.as_ref().err().map(routes::command_error),
+        });
+        result
+    }
+
+    fn record(&self, record: MotionRecord) {
+        if let Some(ref event_publisher) = *self.event_publisher.lock().unwrap() {
+            if let Err(e) = event_publisher.publish_motion(&record) {
+                warn!("Failed to publish motion event {e}");
+            }
+        }
+        if let Some(ref writer) = self.writer {
+            writer.write(record.clone());
+        }
+        let mut records = self.records.lock().unwrap();
+        records.push_back(record);
+        if records.len() > self.capacity {
+            records.pop_front();
+        }
+    }
+
+    /// Matching records from oldest to newest
+    pub fn query(&self, filter: &HistoryFilter) -> Vec<MotionRecord> {
+        let records = self.records.lock().unwrap();
+        let mut records: Vec<_> = records
+            .iter()
+            .filter(|record| filter.matches(record))
+            .cloned()
+            .collect();
+        if let Some(limit) = filter.limit {
+            records.drain(..records.len().saturating_sub(limit));
+        }
+        records
+    }
+}
+
+/// Failed command on serial bus. Other errors such as saving config don't mean lost motors
+fn is_serial_error(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<lss_driver::LssDriverError>().is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(source: CommandSource, action: BlindsAction, outcome: Outcome) -> MotionRecord {
+        let snapshot = PositionSnapshot {
+            state: BlindsState::Other,
+            position: None,
+            tilt: None,
+        };
+        MotionRecord {
+            start: Utc::now(),
+            source,
+            action,
+            before: snapshot.clone(),
+            after: snapshot,
+            duration_seconds: 1.0,
+            outcome,
+            error: None,
+        }
+    }
+
+    fn temp_history_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "blinds_history_{name}_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn keeps_newest_records() {
+        let history = MotionHistory::new(None, 3).unwrap();
+        for open in [0.1, 0.2, 0.3, 0.4, 0.5] {
+            history.record(record(
+                CommandSource::Http,
+                BlindsAction::Partial { open },
+                Outcome::Success,
+            ));
+        }
+        let actions: Vec<_> = history
+            .query(&HistoryFilter::default())
+            .into_iter()
+            .map(|record| record.action)
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                BlindsAction::Partial { open: 0.3 },
+                BlindsAction::Partial { open: 0.4 },
+                BlindsAction::Partial { open: 0.5 },
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_records() {
+        let history = MotionHistory::new(None, 10).unwrap();
+        history.record(record(
+            CommandSource::Http,
+            BlindsAction::Open,
+            Outcome::Success,
+        ));
+        history.record(record(
+            CommandSource::Switch,
+            BlindsAction::Close,
+            Outcome::Error,
+        ));
+        history.record(record(
+            CommandSource::Mqtt,
+            BlindsAction::Open,
+            Outcome::Error,
+        ));
+
+        let filter = HistoryFilter {
+            action: Some("open".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(history.query(&filter).len(), 2);
+
+        let filter = HistoryFilter {
+            outcome: Some(Outcome::Error),
+            source: Some(CommandSource::Switch),
+            ..Default::default()
+        };
+        let records = history.query(&filter);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].action, BlindsAction::Close);
+
+        let filter = HistoryFilter {
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(history.query(&filter)[0].source, CommandSource::Mqtt);
+
+        let filter = HistoryFilter {
+            since: Some(Utc::now() + chrono::Duration::minutes(1)),
+            ..Default::default()
+        };
+        assert!(history.query(&filter).is_empty());
+    }
+
+    #[test]
+    fn history_survives_restart() {
+        let path = temp_history_file("restart");
+        let history = MotionHistory::new(Some(path.clone()), 2).unwrap();
+        for action in [
+            BlindsAction::Open,
+            BlindsAction::Close,
+            BlindsAction::Toggle,
+            BlindsAction::Partial { open: 0.5 },
+        ] {
+            history.record(record(CommandSource::Mqtt, action, Outcome::Success));
+        }
+        drop(history);
+
+        let history = MotionHistory::new(Some(path.clone()), 2).unwrap();
+        let actions: Vec<_> = history
+            .query(&HistoryFilter::default())
+            .into_iter()
+            .map(|record| record.action)
+            .collect();
+        assert_eq!(
+            actions,
+            vec![BlindsAction::Toggle, BlindsAction::Partial { open: 0.5 }]
+        );
+        // file was compacted once it reached twice the capacity
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert_eq!(lines, 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn skips_unreadable_history_lines() {
+        let path = temp_history_file("unreadable");
+        let mut contents = serde_json::to_vec(&record(
+            CommandSource::Http,
+            BlindsAction::Open,
+            Outcome::Success,
+        ))
+        .unwrap();
+        contents.extend_from_slice(b"\n\xff\xfe not utf-8\n");
+        contents.extend_from_slice(
+            &serde_json::to_vec(&record(
+                CommandSource::Http,
+                BlindsAction::Close,
+                Outcome::Success,
+            ))
+            .unwrap(),
+        );
+        contents.push(b'\n');
+        std::fs::write(&path, contents).unwrap();
+
+        let history = MotionHistory::new(Some(path.clone()), 10).unwrap();
+        let actions: Vec<_> = history
+            .query(&HistoryFilter::default())
+            .into_iter()
+            .map(|record| record.action)
+            .collect();
+        assert_eq!(actions, vec![BlindsAction::Open, BlindsAction::Close]);
+        drop(history);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn only_serial_errors_report_lost_connection() {
+        let config_error: anyhow::Error =
+            std::io::Error::new(std::io::ErrorKind::PermissionDenied, "config").into();
+        assert!(!is_serial_error(&config_error));
+        assert!(!is_serial_error(&DriverError::Stopped.into()));
+        assert!(!is_serial_error(
+            &DriverError::WaitingForStopTimedOut.into()
+        ));
+    }
+}
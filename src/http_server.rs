@@ -0,0 +1,387 @@
+use crate::{
+    auth::{ControlAccess, ReadAccess},
+    config::{HttpConfig, HttpTlsConfig},
+    driver::{self, Blinds},
+    error::DriverError,
+    health::HealthChecker,
+    history::{HistoryFilter, MotionHistory},
+    logging::{self, LogLevels},
+    metrics::{self, CommandSource},
+    mqtt_server::MqttConnectionStats,
+    reload::ConfigReloader,
+    routes::{BlindsAction, BlindsCommand},
+};
+use actix_web::{get, middleware::Logger, post, web, App, HttpResponse, HttpServer, Responder};
+use anyhow::Result;
+use log::*;
+use std::{fs::File, io::BufReader, sync::Arc};
+use tokio::sync::Mutex;
+
+#[post("/open_blinds")]
+async fn open_blinds_handler(
+    _access: ControlAccess,
+    driver: web::Data<Mutex<Box<dyn Blinds>>>,
+    history: web::Data<MotionHistory>,
+) -> impl Responder {
+    let mut driver = driver.lock().await;
+    let result = history
+        .run(CommandSource::Http, &BlindsAction::Open, driver.as_mut())
+        .await;
+    if let Err(e) = result {
+        error!("Error while opening blinds {e}");
+        HttpResponse::InternalServerError().finish()
+    } else {
+        HttpResponse::Ok().finish()
+    }
+}
+
+#[post("/close_blinds")]
+async fn close_blinds_handler(
+    _access: ControlAccess,
+    driver: web::Data<Mutex<Box<dyn Blinds>>>,
+    history: web::Data<MotionHistory>,
+) -> impl Responder {
+    let mut driver = driver.lock().await;
+    let result = history
+        .run(CommandSource::Http, &BlindsAction::Close, driver.as_mut())
+        .await;
+    if let Err(e) = result {
+        error!("Error while closing blinds {e}");
+        HttpResponse::InternalServerError().finish()
+    } else {
+        HttpResponse::Ok().finish()
+    }
+}
+
+#[post("/preset/{name}")]
+async fn preset_handler(
+    _access: ControlAccess,
+    name: web::Path<String>,
+    driver: web::Data<Mutex<Box<dyn Blinds>>>,
+    history: web::Data<MotionHistory>,
+) -> impl Responder {
+    let mut driver = driver.lock().await;
+    let action = BlindsAction::Preset {
+        name: name.into_inner(),
+    };
+    match history
+        .run(CommandSource::Http, &action, driver.as_mut())
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => driver_error_response(e, "moving blinds to preset"),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct PositionRequest {
+    position: f32,
+}
+
+#[post("/set_position")]
+async fn set_position_handler(
+    _access: ControlAccess,
+    request: web::Json<PositionRequest>,
+    driver: web::Data<Mutex<Box<dyn Blinds>>>,
+    history: web::Data<MotionHistory>,
+) -> impl Responder {
+    let mut driver = driver.lock().await;
+    let action = BlindsAction::SetPosition {
+        position: request.position,
+    };
+    match history
+        .run(CommandSource::Http, &action, driver.as_mut())
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => driver_error_response(e, "setting position"),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TiltRequest {
+    tilt: f32,
+}
+
+#[post("/set_tilt")]
+async fn set_tilt_handler(
+    _access: ControlAccess,
+    request: web::Json<TiltRequest>,
+    driver: web::Data<Mutex<Box<dyn Blinds>>>,
+    history: web::Data<MotionHistory>,
+) -> impl Responder {
+    let mut driver = driver.lock().await;
+    let action = BlindsAction::SetTilt { tilt: request.tilt };
+    match history
+        .run(CommandSource::Http, &action, driver.as_mut())
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => driver_error_response(e, "setting tilt"),
+    }
+}
+
+/// Any action with optional motion mode, same payload as MQTT `command` topic
+#[post("/command")]
+async fn command_handler(
+    _access: ControlAccess,
+    command: web::Json<BlindsCommand>,
+    driver: web::Data<Mutex<Box<dyn Blinds>>>,
+    history: web::Data<MotionHistory>,
+) -> impl Responder {
+    let mut driver = driver.lock().await;
+    driver.set_motion_mode(command.mode);
+    let result = history
+        .run(CommandSource::Http, &command.action, driver.as_mut())
+        .await;
+    driver.set_motion_mode(None);
+    match result {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => driver_error_response(e, "running command"),
+    }
+}
+
+/// Stop running command without waiting for the driver
+#[post("/stop")]
+async fn stop_handler(_access: ControlAccess) -> impl Responder {
+    info!("Stop requested");
+    driver::request_stop();
+    HttpResponse::Ok().finish()
+}
+
+#[post("/calibrate")]
+async fn calibrate_handler(
+    _access: ControlAccess,
+    driver: web::Data<Mutex<Box<dyn Blinds>>>,
+    history: web::Data<MotionHistory>,
+) -> impl Responder {
+    let mut driver = driver.lock().await;
+    match history
+        .run(
+            CommandSource::Http,
+            &BlindsAction::Calibrate,
+            driver.as_mut(),
+        )
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => driver_error_response(e, "calibrating"),
+    }
+}
+
+/// Map rejected commands to client errors and everything else to internal error
+fn driver_error_response(error: anyhow::Error, action: &str) -> HttpResponse {
+    match error.downcast_ref::<DriverError>() {
+        Some(DriverError::UnknownPreset(_)) => HttpResponse::NotFound().body(error.to_string()),
+        Some(DriverError::PartialPositionOutOfRange) => {
+            HttpResponse::BadRequest().body(error.to_string())
+        }
+        Some(
+            DriverError::SlatsNotOpen
+            | DriverError::CurtainNotClosed
+            | DriverError::TiltNotSupported
+            | DriverError::Stopped
+            | DriverError::CalibrationNotStarted
+            | DriverError::ManualCalibrationNeeded,
+        ) => HttpResponse::Conflict().body(error.to_string()),
+        Some(DriverError::Disconnected) => {
+            HttpResponse::ServiceUnavailable().body(error.to_string())
+        }
+        _ => {
+            error!("Error while {action} {error}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[post("/reload_config")]
+async fn reload_config_handler(
+    _access: ControlAccess,
+    reloader: web::Data<ConfigReloader>,
+) -> impl Responder {
+    if let Err(e) = reloader.reload().await {
+        error!("Error while reloading config {e}");
+        HttpResponse::BadRequest().body(e.to_string())
+    } else {
+        HttpResponse::Ok().finish()
+    }
+}
+
+#[get("/state")]
+async fn state_handler(_access: ReadAccess, history: web::Data<MotionHistory>) -> impl Responder {
+    // served from last published status so this doesn't wait for running commands
+    match history.status_model().last_status() {
+        Some(status) => HttpResponse::Ok().json(status),
+        None => HttpResponse::ServiceUnavailable().finish(),
+    }
+}
+
+#[get("/history")]
+async fn history_handler(
+    _access: ReadAccess,
+    filter: web::Query<HistoryFilter>,
+    history: web::Data<MotionHistory>,
+) -> impl Responder {
+    web::Json(history.query(&filter))
+}
+
+/// Process is alive. Not authenticated
+#[get("/healthz")]
+async fn healthz_handler() -> impl Responder {
+    HttpResponse::Ok().body("ok")
+}
+
+/// Motors, MQTT and calibration are all fine. Not authenticated
+#[get("/readyz")]
+async fn readyz_handler(health_checker: web::Data<HealthChecker>) -> impl Responder {
+    let readiness = health_checker.readiness().await;
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+#[get("/log_levels")]
+async fn log_levels_handler(_access: ReadAccess) -> impl Responder {
+    web::Json(logging::levels())
+}
+
+/// Replace levels until next restart or config reload
+#[post("/log_levels")]
+async fn set_log_levels_handler(
+    _access: ControlAccess,
+    levels: web::Json<LogLevels>,
+) -> impl Responder {
+    logging::set_levels(levels.into_inner());
+    HttpResponse::Ok().finish()
+}
+
+#[get("/metrics")]
+async fn metrics_handler(_access: ReadAccess) -> impl Responder {
+    match metrics::render() {
+        Ok(metrics) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(metrics),
+        Err(e) => {
+            error!("Failed to render metrics {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[get("/mqtt_status")]
+async fn mqtt_status_handler(
+    _access: ReadAccess,
+    mqtt_stats: web::Data<MqttConnectionStats>,
+) -> impl Responder {
+    web::Json(mqtt_stats.status())
+}
+
+/// Run HTTP server until it's stopped
+///
+/// If HTTP is disabled this waits for ctrl-c instead so that the MQTT service keeps running
+pub async fn run_http_server(
+    config: HttpConfig,
+    driver: Arc<Mutex<Box<dyn Blinds>>>,
+    reloader: Arc<ConfigReloader>,
+    mqtt_stats: Arc<MqttConnectionStats>,
+    history: Arc<MotionHistory>,
+    health_checker: Arc<HealthChecker>,
+) -> Result<()> {
+    if !config.enabled {
+        info!("HTTP server disabled");
+        tokio::signal::ctrl_c().await?;
+        return Ok(());
+    }
+
+    let driver = web::Data::from(driver);
+    let reloader = web::Data::from(reloader);
+    let mqtt_stats = web::Data::from(mqtt_stats);
+    let history = web::Data::from(history);
+    let health_checker = web::Data::from(health_checker);
+    let auth_config = config.auth.clone().map(web::Data::new);
+    if auth_config.is_none() {
+        warn!("HTTP authentication is disabled");
+    }
+
+    let mut server = HttpServer::new(move || {
+        let app = App::new()
+            .wrap(Logger::new("%r %s %U"))
+            .service(open_blinds_handler)
+            .service(close_blinds_handler)
+            .service(preset_handler)
+            .service(set_position_handler)
+            .service(set_tilt_handler)
+            .service(command_handler)
+            .service(stop_handler)
+            .service(calibrate_handler)
+            .service(reload_config_handler)
+            .service(state_handler)
+            .service(mqtt_status_handler)
+            .service(metrics_handler)
+            .service(history_handler)
+            .service(log_levels_handler)
+            .service(set_log_levels_handler)
+            .service(healthz_handler)
+            .service(readyz_handler)
+            .app_data(driver.clone())
+            .app_data(reloader.clone())
+            .app_data(mqtt_stats.clone())
+            .app_data(history.clone())
+            .app_data(health_checker.clone());
+        match auth_config {
+            Some(ref auth_config) => app.app_data(auth_config.clone()),
+            None => app,
+        }
+    });
+
+    let tls_config = config.tls.as_ref().map(load_tls_config).transpose()?;
+    for address in &config.bind_addresses {
+        if let Some(ref tls_config) = tls_config {
+            info!("Binding on address: https://{}:{}", address, config.port);
+            server = server.bind_rustls((address.as_str(), config.port), tls_config.clone())?;
+        } else {
+            info!("Binding on address: http://{}:{}", address, config.port);
+            server = server.bind((address.as_str(), config.port))?;
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(ref socket_path) = config.unix_socket {
+        if socket_path.exists() {
+            // remove stale socket from previous run
+            std::fs::remove_file(socket_path)?;
+        }
+        info!("Binding on unix socket: {}", socket_path.display());
+        server = server.bind_uds(socket_path)?;
+    }
+
+    server.run().await?;
+    Ok(())
+}
+
+fn load_tls_config(config: &HttpTlsConfig) -> Result<rustls::ServerConfig> {
+    let mut cert_reader = BufReader::new(File::open(&config.cert_file)?);
+    let certs = rustls_pemfile::certs(&mut cert_reader)?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+
+    let mut key_reader = BufReader::new(File::open(&config.key_file)?);
+    let key = rustls_pemfile::read_all(&mut key_reader)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("No private key found in {:?}", config.key_file))?;
+
+    let tls_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(tls_config)
+}
@@ -0,0 +1,301 @@
+use crate::config::{BlindsConfig, ConfigOverrides, LogFormat};
+use anyhow::Result;
+use log::{LevelFilter, Log, Metadata, Record};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io::Write, sync::RwLock};
+
+#[cfg(unix)]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+static LOGGER: OnceCell<Logger> = OnceCell::new();
+
+/// Default level with overrides for module paths such as `blinds::driver`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogLevels {
+    pub default: LevelFilter,
+    #[serde(default)]
+    pub modules: BTreeMap<String, LevelFilter>,
+}
+
+impl LogLevels {
+    /// Levels from `RUST_LOG` if set, otherwise from config
+    ///
+    /// `--log-level` or `BLINDS_LOG_LEVEL` wins over `RUST_LOG`
+    pub fn from_config(config: &BlindsConfig, overrides: &ConfigOverrides) -> Result<Self> {
+        let rust_log = std::env::var("RUST_LOG").ok();
+        Self::select(config, overrides.log_level.is_some(), rust_log.as_deref())
+    }
+
+    fn select(
+        config: &BlindsConfig,
+        level_overridden: bool,
+        rust_log: Option<&str>,
+    ) -> Result<Self> {
+        match rust_log {
+            Some(directives) if !level_overridden && !directives.trim().is_empty() => {
+                Self::parse(directives)
+            }
+            _ => Ok(Self {
+                default: config.log_level,
+                modules: config.log_modules.clone(),
+            }),
+        }
+    }
+
+    /// Parse `RUST_LOG` style directives such as `info,blinds::driver=trace`
+    ///
+    /// Module without level logs everything like in `env_logger`
+    pub fn parse(directives: &str) -> Result<Self> {
+        let mut levels = Self {
+            default: LevelFilter::Error,
+            modules: BTreeMap::new(),
+        };
+        for directive in directives.split(',').map(str::trim) {
+            if directive.is_empty() {
+                continue;
+            }
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    levels
+                        .modules
+                        .insert(module.trim().to_owned(), level.trim().parse()?);
+                }
+                None => match directive.parse() {
+                    Ok(level) => levels.default = level,
+                    Err(_) => {
+                        levels
+                            .modules
+                            .insert(directive.to_owned(), LevelFilter::Trace);
+                    }
+                },
+            }
+        }
+        Ok(levels)
+    }
+
+    /// Level of the longest module matching target
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                target
+                    .strip_prefix(module.as_str())
+                    .map(|rest| rest.is_empty() || rest.starts_with("::"))
+                    .unwrap_or(false)
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules.values().copied().fold(self.default, Ord::max)
+    }
+}
+
+struct Logger {
+    format: LogFormat,
+    levels: RwLock<LogLevels>,
+    #[cfg(unix)]
+    journal: Option<std::os::unix::net::UnixDatagram>,
+}
+
+impl Logger {
+    fn new(format: LogFormat, levels: LogLevels) -> Result<Self> {
+        #[cfg(unix)]
+        let journal = match format {
+            LogFormat::Journald => Some(std::os::unix::net::UnixDatagram::unbound()?),
+            LogFormat::Human | LogFormat::Json => None,
+        };
+        #[cfg(not(unix))]
+        if format == LogFormat::Journald {
+            anyhow::bail!("journald logging is only supported on unix");
+        }
+        Ok(Self {
+            format,
+            levels: RwLock::new(levels),
+            #[cfg(unix)]
+            journal,
+        })
+    }
+
+    fn human_line(record: &Record) -> String {
+        format!(
+            "{} {:<5} [{}] {}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            record.level(),
+            record.target(),
+            record.args()
+        )
+    }
+
+    fn json_line(record: &Record) -> String {
+        serde_json::json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        })
+        .to_string()
+    }
+
+    #[cfg(unix)]
+    fn log_journald(&self, record: &Record) {
+        let sent = self.journal.as_ref().map(|journal| {
+            journal
+                .send_to(&journal_entry(record), JOURNALD_SOCKET)
+                .is_ok()
+        });
+        if sent != Some(true) {
+            // journald is not running. Don't lose the message
+            let _ = writeln!(std::io::stderr(), "{}", Self::human_line(record));
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.levels.read().unwrap().level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = match self.format {
+            LogFormat::Human => Self::human_line(record),
+            LogFormat::Json => Self::json_line(record),
+            LogFormat::Journald => {
+                #[cfg(unix)]
+                self.log_journald(record);
+                return;
+            }
+        };
+        // ignore closed stdout instead of panicking like println
+        let _ = writeln!(std::io::stdout().lock(), "{line}");
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+    }
+}
+
+/// Native journal protocol entry
+///
+/// Values with newlines are sent length prefixed
+#[cfg(unix)]
+fn journal_entry(record: &Record) -> Vec<u8> {
+    fn add_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+        entry.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+
+    let priority = match record.level() {
+        log::Level::Error => "3",
+        log::Level::Warn => "4",
+        log::Level::Info => "6",
+        log::Level::Debug | log::Level::Trace => "7",
+    };
+    let mut entry = vec![];
+    add_field(&mut entry, "PRIORITY", priority);
+    add_field(&mut entry, "MESSAGE", &record.args().to_string());
+    add_field(&mut entry, "SYSLOG_IDENTIFIER", "blinds");
+    add_field(&mut entry, "TARGET", record.target());
+    if let Some(file) = record.file() {
+        add_field(&mut entry, "CODE_FILE", file);
+    }
+    if let Some(line) = record.line() {
+        add_field(&mut entry, "CODE_LINE", &line.to_string());
+    }
+    entry
+}
+
+/// Install global logger
+pub fn init(format: LogFormat, levels: LogLevels) -> Result<()> {
+    let max_level = levels.max_level();
+    let logger = LOGGER.get_or_try_init(|| Logger::new(format, levels))?;
+    log::set_logger(logger)?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Change levels of running logger
+///
+/// Output format can only be changed by restarting
+pub fn set_levels(levels: LogLevels) {
+    if let Some(logger) = LOGGER.get() {
+        log::set_max_level(levels.max_level());
+        *logger.levels.write().unwrap() = levels;
+        log::info!("Log levels changed");
+    }
+}
+
+pub fn levels() -> Option<LogLevels> {
+    LOGGER
+        .get()
+        .map(|logger| logger.levels.read().unwrap().clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_rust_log_directives() {
+        let levels = LogLevels::parse("info, blinds::driver=trace,mqtt_router").unwrap();
+        assert_eq!(levels.default, LevelFilter::Info);
+        assert_eq!(
+            levels.modules.get("blinds::driver"),
+            Some(&LevelFilter::Trace)
+        );
+        assert_eq!(levels.modules.get("mqtt_router"), Some(&LevelFilter::Trace));
+        assert!(LogLevels::parse("blinds=loud").is_err());
+    }
+
+    #[test]
+    fn longest_module_wins() {
+        let levels = LogLevels::parse("warn,blinds=info,blinds::driver=debug").unwrap();
+        assert_eq!(levels.level_for("actix_web::server"), LevelFilter::Warn);
+        assert_eq!(levels.level_for("blinds"), LevelFilter::Info);
+        assert_eq!(levels.level_for("blinds::routes"), LevelFilter::Info);
+        assert_eq!(
+            levels.level_for("blinds::driver::living_room_blinds"),
+            LevelFilter::Debug
+        );
+        // prefix has to end at module boundary
+        assert_eq!(levels.level_for("blinds_extra"), LevelFilter::Warn);
+        assert_eq!(levels.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn explicit_level_wins_over_rust_log() {
+        let mut config = BlindsConfig::default();
+        config.log_level = LevelFilter::Warn;
+        config
+            .log_modules
+            .insert("blinds::driver".to_owned(), LevelFilter::Debug);
+
+        let from_rust_log = LogLevels::select(&config, false, Some("trace")).unwrap();
+        assert_eq!(from_rust_log.default, LevelFilter::Trace);
+        assert!(from_rust_log.modules.is_empty());
+
+        // apply_overrides already put the explicit level in config
+        let explicit = LogLevels::select(&config, true, Some("trace")).unwrap();
+        assert_eq!(explicit.default, LevelFilter::Warn);
+        assert_eq!(
+            explicit.modules.get("blinds::driver"),
+            Some(&LevelFilter::Debug)
+        );
+
+        let unset = LogLevels::select(&config, false, Some(" ")).unwrap();
+        assert_eq!(unset, explicit);
+    }
+}
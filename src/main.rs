@@ -1,25 +1,42 @@
+mod auth;
 mod config;
+mod config_validation;
+mod ctl;
 mod driver;
 mod error;
+mod health;
+mod history;
+mod http_server;
+mod logging;
+mod metrics;
+mod mqtt_client;
 mod mqtt_server;
+mod reload;
 mod routes;
+mod status;
+mod supervisor;
 
-use actix_web::{middleware::Logger, post, web, App, HttpResponse, HttpServer, Responder};
 use anyhow::Result;
-use clap::Parser;
-use config::BlindsConfig;
-use driver::Blinds;
+use clap::{Parser, Subcommand};
+use config::{BlindsConfig, ConfigOverrides};
+use ctl::CtlArgs;
+use driver::{Blinds, DiagnosticsCommand};
+use health::HealthChecker;
+use history::MotionHistory;
 use log::*;
+use mqtt_server::StatePublisher;
+use reload::ConfigReloader;
 use std::{path::PathBuf, sync::Arc};
+use supervisor::ConnectionSupervisor;
 use tokio::sync::Mutex;
 
-use mqtt_server::start_mqtt_service;
+use mqtt_server::MqttConnectionStats;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     /// path to config file
-    #[clap(long)]
+    #[clap(long, env = "BLINDS_CONFIG")]
     config: Option<PathBuf>,
     /// create default config
     #[clap(long)]
@@ -27,46 +44,116 @@ struct Args {
     /// start with calibration
     #[clap(long)]
     run_calibration: bool,
+    /// check config file for problems and exit
+    #[clap(long)]
+    check_config: bool,
+    /// print JSON Schema of config file and exit
+    #[clap(long)]
+    print_config_schema: bool,
+    /// override MQTT broker host
+    #[clap(long, env = "BLINDS_BROKER_HOST")]
+    broker_host: Option<String>,
+    /// override MQTT broker port
+    #[clap(long, env = "BLINDS_BROKER_PORT")]
+    broker_port: Option<u16>,
+    /// override MQTT username
+    #[clap(long, env = "BLINDS_MQTT_USERNAME")]
+    mqtt_username: Option<String>,
+    /// override MQTT password
+    #[clap(long, env = "BLINDS_MQTT_PASSWORD", hide_env_values = true)]
+    mqtt_password: Option<String>,
+    /// override serial port of motors
+    #[clap(long, env = "BLINDS_SERIAL_PORT")]
+    serial_port: Option<String>,
+    /// override HTTP bind addresses
+    #[clap(long, env = "BLINDS_HTTP_BIND", use_value_delimiter = true)]
+    http_bind: Vec<String>,
+    /// override HTTP port
+    #[clap(long, env = "BLINDS_HTTP_PORT")]
+    http_port: Option<u16>,
+    /// override log level
+    #[clap(long, env = "BLINDS_LOG_LEVEL")]
+    log_level: Option<LevelFilter>,
+    #[clap(subcommand)]
+    command: Option<Command>,
 }
 
-#[post("/open_blinds")]
-async fn open_blinds_handler(driver: web::Data<Mutex<Box<dyn Blinds>>>) -> impl Responder {
-    let mut driver = driver.lock().await;
-    if let Err(e) = driver.open().await {
-        error!("Error while opening blinds {e}");
-        HttpResponse::InternalServerError().finish()
-    } else {
-        HttpResponse::Ok().finish()
-    }
+/// Tools that run instead of the service
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[clap(flatten)]
+    Diagnostics(DiagnosticsCommand),
+    /// control running service over HTTP
+    Ctl(CtlArgs),
 }
 
-#[post("/close_blinds")]
-async fn close_blinds_handler(driver: web::Data<Mutex<Box<dyn Blinds>>>) -> impl Responder {
-    let mut driver = driver.lock().await;
-    if let Err(e) = driver.close().await {
-        error!("Error while closing blinds {e}");
-        HttpResponse::InternalServerError().finish()
-    } else {
-        HttpResponse::Ok().finish()
+impl Args {
+    fn config_overrides(&self) -> ConfigOverrides {
+        ConfigOverrides {
+            broker_host: self.broker_host.clone(),
+            broker_port: self.broker_port,
+            mqtt_username: self.mqtt_username.clone(),
+            mqtt_password: self.mqtt_password.clone(),
+            serial_port: self.serial_port.clone(),
+            http_bind_addresses: self.http_bind.clone(),
+            http_port: self.http_port,
+            log_level: self.log_level,
+        }
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    simplelog::TermLogger::init(
-        simplelog::LevelFilter::Info,
-        simplelog::Config::default(),
-        simplelog::TerminalMode::Stdout,
-        simplelog::ColorChoice::Auto,
-    )?;
 
-    info!("Starting blinds");
+    if let Some(Command::Ctl(ref ctl)) = args.command {
+        return ctl.run().await;
+    }
+
+    if args.print_config_schema {
+        let schema = BlindsConfig::json_schema();
+        println!("{}", serde_json::to_string_pretty(&schema)?);
+        return Ok(());
+    }
 
     let config_path = args
         .config
+        .clone()
         .unwrap_or_else(|| BlindsConfig::default_config_location().unwrap());
 
+    if args.check_config {
+        let problems =
+            config_validation::check_config_file(&config_path, &args.config_overrides()).await?;
+        if problems.is_empty() {
+            println!("Config {} is valid", config_path.display());
+            return Ok(());
+        }
+        for problem in &problems {
+            println!("{problem}");
+        }
+        anyhow::bail!(
+            "Found {} problems in config {}",
+            problems.len(),
+            config_path.display()
+        );
+    }
+
+    if let Some(Command::Diagnostics(ref command)) = args.command {
+        // don't write default config just to look at motors
+        let mut config = if config_path.exists() {
+            BlindsConfig::load(&config_path).await?
+        } else {
+            BlindsConfig::default()
+        };
+        let overrides = args.config_overrides();
+        config.apply_overrides(&overrides);
+        logging::init(
+            config.log_format,
+            logging::LogLevels::from_config(&config, &overrides)?,
+        )?;
+        return command.run(&config).await;
+    }
+
     let mut new_config = false;
     if args.create_default_config {
         BlindsConfig::default().save(&config_path).await?;
@@ -76,47 +163,152 @@ async fn main() -> Result<()> {
         new_config = true;
     }
 
-    let config = BlindsConfig::load(&config_path).await?;
+    let overrides = args.config_overrides();
+    let mut config = BlindsConfig::load(&config_path).await?;
+    config.apply_overrides(&overrides);
 
-    let (mut driver, mqtt_config) = config.driver_from_config().await?;
+    logging::init(
+        config.log_format,
+        logging::LogLevels::from_config(&config, &overrides)?,
+    )?;
 
-    let were_motors_rebooted = driver.were_motors_rebooted().await?;
-    let needs_calibration = driver.needs_calibration();
-    if new_config || args.run_calibration || were_motors_rebooted || needs_calibration {
-        if were_motors_rebooted {
-            warn!("Motors seems to have been rebooted since the last run.");
-        }
-        if new_config {
-            warn!("Fresh config written. Running calibration.");
-        }
-        driver.calibrate(&config_path).await?;
+    info!("Starting blinds");
+
+    let http_config = config.http.clone();
+    let history = Arc::new(MotionHistory::new(
+        Some(config.history.file_path(&config_path)),
+        config.history.capacity,
+    )?);
+    let (mut driver, mqtt_config) = config.driver_from_config(&config_path).await?;
+    // `GET /state` serves last published state even without MQTT
+    driver.set_state_publisher(StatePublisher::local(history.status_model()));
+    driver.publish_state();
+
+    if new_config {
+        warn!("Fresh config written. Blinds need calibration.");
     }
+    calibrate_on_start(driver.as_mut(), args.run_calibration).await;
 
-    let address = format!("{}:{}", "0.0.0.0", 8080);
-    info!("Binding on address: {address}");
     let driver = Arc::new(Mutex::new(driver));
+    let supervisor = ConnectionSupervisor::start(driver.clone());
+    history.set_supervisor(supervisor.clone());
 
-    let state_publisher =
-        start_mqtt_service(driver.clone(), mqtt_config).expect("Failed to start mqtt server");
-
-    driver.lock().await.set_state_publisher(state_publisher);
-    let driver = web::Data::from(driver);
-
-    HttpServer::new(move || {
-        App::new()
-            .wrap(Logger::new("%r %s %U"))
-            .service(open_blinds_handler)
-            .service(close_blinds_handler)
-            .app_data(driver.clone())
-    })
-    .bind(address)?
-    .run()
+    let mqtt_stats = Arc::new(MqttConnectionStats::default());
+    let reloader = Arc::new(ConfigReloader::new(
+        config_path,
+        overrides,
+        driver.clone(),
+        mqtt_stats.clone(),
+        history.clone(),
+    ));
+    // missing password or certificate files shouldn't take down HTTP control
+    if let Err(e) = reloader.start_mqtt(mqtt_config).await {
+        error!("Failed to start MQTT service. Running without MQTT until config is reloaded {e}");
+    }
+    #[cfg(unix)]
+    reload::reload_on_sighup(reloader.clone())?;
+
+    let health_checker = Arc::new(HealthChecker::new(
+        driver.clone(),
+        mqtt_stats.clone(),
+        history.clone(),
+        supervisor,
+    ));
+    #[cfg(unix)]
+    {
+        health::systemd::start_watchdog(health_checker.clone());
+        health::systemd::notify_ready();
+    }
+
+    http_server::run_http_server(
+        http_config,
+        driver,
+        reloader,
+        mqtt_stats,
+        history,
+        health_checker,
+    )
     .await?;
     Ok(())
 }
 
+/// Calibrate when requested or when config or motors need it
+///
+/// Only `--run-calibration` waits for slats to be moved by hand. Failure is logged and the
+/// service keeps running uncalibrated. `/readyz` reports that until calibration is
+/// requested over HTTP or MQTT
+async fn calibrate_on_start(driver: &mut dyn Blinds, force: bool) {
+    match driver.were_motors_rebooted().await {
+        Ok(true) => warn!("Motors seems to have been rebooted since the last run."),
+        Ok(false) => (),
+        Err(e) => error!("Failed to check if motors were rebooted {e}"),
+    }
+    let result = if force {
+        driver.calibrate().await
+    } else if driver.needs_calibration() {
+        driver.calibrate_unattended().await
+    } else {
+        return;
+    };
+    if let Err(e) = result {
+        error!("Calibration on start failed. Running uncalibrated {e}");
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use super::*;
+
     #[test]
     fn empty_test() {}
+
+    #[tokio::test]
+    async fn failed_start_calibration_keeps_running_uncalibrated() {
+        use crate::routes::test::{fake_blinds_on_bus, FakeBus};
+        use std::sync::atomic::Ordering;
+
+        let bus = std::sync::Arc::new(FakeBus::default());
+        bus.rebooted.store(true, Ordering::SeqCst);
+        bus.unattended.store(true, Ordering::SeqCst);
+        let (blinds, calls) = fake_blinds_on_bus(bus);
+        let mut blinds = blinds.lock().await;
+
+        calibrate_on_start(blinds.as_mut(), false).await;
+        calibrate_on_start(blinds.as_mut(), true).await;
+        assert!(blinds.needs_calibration());
+        assert_eq!(*calls.lock().unwrap(), vec!["calibrate", "calibrate"]);
+    }
+
+    /// Only test that touches `BLINDS_*` variables so parallel tests don't see them
+    #[test]
+    fn command_line_takes_precedence_over_environment() {
+        std::env::set_var("BLINDS_BROKER_HOST", "env-broker");
+        std::env::set_var("BLINDS_BROKER_PORT", "1884");
+        std::env::set_var("BLINDS_HTTP_BIND", "127.0.0.1,::1");
+        let from_env = Args::try_parse_from(["blinds"]).unwrap().config_overrides();
+        let from_cli = Args::try_parse_from([
+            "blinds",
+            "--broker-host",
+            "cli-broker",
+            "--http-bind",
+            "192.168.1.2,10.0.0.2",
+        ])
+        .unwrap()
+        .config_overrides();
+        std::env::remove_var("BLINDS_BROKER_HOST");
+        std::env::remove_var("BLINDS_BROKER_PORT");
+        std::env::remove_var("BLINDS_HTTP_BIND");
+
+        assert_eq!(from_env.broker_host.as_deref(), Some("env-broker"));
+        assert_eq!(from_env.broker_port, Some(1884));
+        assert_eq!(from_env.http_bind_addresses, vec!["127.0.0.1", "::1"]);
+        assert_eq!(from_cli.broker_host.as_deref(), Some("cli-broker"));
+        // environment still applies to fields without a flag
+        assert_eq!(from_cli.broker_port, Some(1884));
+        assert_eq!(
+            from_cli.http_bind_addresses,
+            vec!["192.168.1.2", "10.0.0.2"]
+        );
+        assert_eq!(from_cli.http_port, None);
+    }
 }
@@ -0,0 +1,151 @@
+use crate::{driver::BlindsState, error::DriverError};
+pub use blinds_protocol::CommandSource;
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_gauge, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, Gauge, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+static COMMANDS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "blinds_commands_total",
+        "Commands by source, operation and result",
+        &["source", "operation", "result"]
+    )
+    .expect("Failed to register metric")
+});
+
+static MOTION_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "blinds_motion_duration_seconds",
+        "Time it took to run command by operation",
+        &["operation"],
+        vec![0.5, 1.0, 2.0, 5.0, 10.0, 15.0, 20.0, 30.0, 45.0, 60.0]
+    )
+    .expect("Failed to register metric")
+});
+
+static WAIT_FOR_STOP_TIMEOUTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "blinds_wait_for_stop_timeouts_total",
+        "Motors that didn't stop in time"
+    )
+    .expect("Failed to register metric")
+});
+
+static BAD_MOTOR_STATUS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "blinds_bad_motor_status_total",
+        "Moves aborted because of motor status",
+        &["status"]
+    )
+    .expect("Failed to register metric")
+});
+
+static MQTT_RECONNECTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("blinds_mqtt_reconnects_total", "Reconnects to MQTT broker")
+        .expect("Failed to register metric")
+});
+
+static MQTT_CONNECTED: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("blinds_mqtt_connected", "1 when connected to MQTT broker")
+        .expect("Failed to register metric")
+});
+
+static STATE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "blinds_state",
+        "1 for current state of the blinds",
+        &["state"]
+    )
+    .expect("Failed to register metric")
+});
+
+static LAST_CALIBRATION: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "blinds_last_calibration_timestamp_seconds",
+        "Unix time of last finished calibration"
+    )
+    .expect("Failed to register metric")
+});
+
+/// Measures a single command
+pub struct CommandTimer {
+    source: CommandSource,
+    operation: &'static str,
+    start: Instant,
+}
+
+impl CommandTimer {
+    pub fn start(source: CommandSource, operation: &'static str) -> Self {
+        Self {
+            source,
+            operation,
+            start: Instant::now(),
+        }
+    }
+
+    /// Count command and record its duration
+    ///
+    /// Result label is `success` or `DriverError` variant, `error` for other errors
+    pub fn finish(self, result: &anyhow::Result<()>) {
+        let result_label = match result {
+            Ok(()) => "success",
+            Err(e) => e
+                .downcast_ref::<DriverError>()
+                .map(DriverError::kind)
+                .unwrap_or("error"),
+        };
+        COMMANDS
+            .with_label_values(&[self.source.label(), self.operation, result_label])
+            .inc();
+        MOTION_DURATION
+            .with_label_values(&[self.operation])
+            .observe(self.start.elapsed().as_secs_f64());
+    }
+}
+
+pub fn record_wait_for_stop_timeout() {
+    WAIT_FOR_STOP_TIMEOUTS.inc();
+}
+
+pub fn record_bad_motor_status(status: &lss_driver::MotorStatus) {
+    BAD_MOTOR_STATUS
+        .with_label_values(&[&format!("{status:?}")])
+        .inc();
+}
+
+pub fn record_mqtt_connected() {
+    MQTT_CONNECTED.set(1);
+}
+
+pub fn record_mqtt_reconnect() {
+    MQTT_RECONNECTS.inc();
+}
+
+pub fn record_mqtt_disconnected() {
+    MQTT_CONNECTED.set(0);
+}
+
+pub fn record_state(state: BlindsState) {
+    for other in BlindsState::ALL {
+        STATE
+            .with_label_values(&[other.label()])
+            .set(i64::from(other == state));
+    }
+}
+
+pub fn record_calibration() {
+    if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        LAST_CALIBRATION.set(now.as_secs_f64());
+    }
+}
+
+/// All metrics in Prometheus text format
+pub fn render() -> anyhow::Result<String> {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
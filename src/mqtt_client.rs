@@ -0,0 +1,114 @@
+use anyhow::Result;
+use bytes::Bytes;
+use rumqttc::{
+    v5::{
+        self,
+        mqttbytes::v5::{Filter, PublishProperties},
+    },
+    QoS, SubscribeFilter,
+};
+
+/// MQTT client for either supported protocol version
+#[derive(Clone)]
+pub enum MqttClient {
+    V3(rumqttc::AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+/// Properties of an incoming message
+///
+/// Only MQTT v5 messages carry these
+#[derive(Debug, Clone, Default)]
+pub struct MessageProperties {
+    pub response_topic: Option<String>,
+    pub correlation_data: Option<Bytes>,
+    /// Value of `source` user property
+    pub source: Option<String>,
+}
+
+impl MessageProperties {
+    pub fn from_v5(properties: Option<&PublishProperties>) -> Self {
+        match properties {
+            Some(properties) => Self {
+                response_topic: properties.response_topic.clone(),
+                correlation_data: properties.correlation_data.clone(),
+                source: properties
+                    .user_properties
+                    .iter()
+                    .find(|(key, _)| key == "source")
+                    .map(|(_, value)| value.clone()),
+            },
+            None => Self::default(),
+        }
+    }
+}
+
+impl MqttClient {
+    /// Queue message without waiting
+    pub fn try_publish(&self, topic: &str, payload: Vec<u8>) -> Result<()> {
+        match self {
+            MqttClient::V3(client) => client.try_publish(topic, QoS::AtMostOnce, false, payload)?,
+            MqttClient::V5(client) => {
+                client.try_publish(topic, v5::mqttbytes::QoS::AtMostOnce, false, payload)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue response message
+    ///
+    /// Correlation data is attached as MQTT v5 property when connected over v5
+    pub fn try_publish_response(
+        &self,
+        topic: &str,
+        payload: Vec<u8>,
+        correlation_data: Option<Bytes>,
+    ) -> Result<()> {
+        match self {
+            MqttClient::V5(client) if correlation_data.is_some() => {
+                let properties = PublishProperties {
+                    correlation_data,
+                    ..Default::default()
+                };
+                client.try_publish_with_properties(
+                    topic,
+                    v5::mqttbytes::QoS::AtMostOnce,
+                    false,
+                    payload,
+                    properties,
+                )?;
+            }
+            _ => self.try_publish(topic, payload)?,
+        }
+        Ok(())
+    }
+
+    pub async fn subscribe_many(&self, topics: impl Iterator<Item = String>) -> Result<()> {
+        match self {
+            MqttClient::V3(client) => {
+                client
+                    .subscribe_many(topics.map(|path| SubscribeFilter {
+                        path,
+                        qos: QoS::AtMostOnce,
+                    }))
+                    .await?
+            }
+            MqttClient::V5(client) => {
+                client
+                    .subscribe_many(
+                        topics.map(|path| Filter::new(path, v5::mqttbytes::QoS::AtMostOnce)),
+                    )
+                    .await?
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn disconnect(&self) -> Result<()> {
+        match self {
+            MqttClient::V3(client) => client.disconnect().await?,
+            MqttClient::V5(client) => client.disconnect().await?,
+        }
+        Ok(())
+    }
+}
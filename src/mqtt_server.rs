@@ -1,142 +1,520 @@
-use super::routes::{BlindsHandler, SwitchHandler};
+use super::routes::{BlindsHandler, BlindsTopic, CommandResponder, SwitchHandler};
 use crate::{
-    config::MqttConfig,
-    driver::{Blinds, BlindsState},
+    config::{MqttConfig, MqttProtocol},
+    driver::Blinds,
+    history::{MotionHistory, MotionRecord},
+    metrics,
+    mqtt_client::{MessageProperties, MqttClient},
+    status::StatusModel,
 };
 use anyhow::Result;
+use blinds_protocol::{BlindsStatus, StateUpdate};
+use bytes::Bytes;
 use log::*;
 use mqtt_router::Router;
-use rumqttc::{AsyncClient, ConnAck, Event, Incoming, MqttOptions, Publish, QoS, SubscribeFilter};
-use std::{sync::Arc, time::Duration};
-use tokio::sync::{mpsc::unbounded_channel, Mutex};
+use rand::Rng;
+use rumqttc::{
+    v5::{self, mqttbytes::v5::Packet},
+    AsyncClient, Event, EventLoop, Incoming, MqttOptions, Transport,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        Mutex,
+    },
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+
+struct IncomingMessage {
+    topic: String,
+    payload: Bytes,
+    properties: MessageProperties,
+}
 
 enum MqttUpdate {
-    Message(Publish),
-    Reconnection(ConnAck),
+    Message(IncomingMessage),
+    Reconnection,
+}
+
+/// Broker that doesn't take disconnect in this time is left behind
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Running MQTT connection with its event loop and router tasks
+pub struct MqttService {
+    client: MqttClient,
+    config: MqttConfig,
+    event_loop_task: JoinHandle<()>,
+    router_task: JoinHandle<()>,
+}
+
+impl MqttService {
+    pub fn config(&self) -> &MqttConfig {
+        &self.config
+    }
+
+    pub fn state_publisher(&self, status: Arc<StatusModel>) -> StatePublisher {
+        let update_topic = BlindsTopic::State.topic(&self.config.base_route);
+        let status_topic = BlindsTopic::Status.topic(&self.config.base_route);
+        StatePublisher::new(self.client.clone(), update_topic, status_topic, status)
+    }
+
+    pub fn event_publisher(&self) -> EventPublisher {
+        let event_topic = BlindsTopic::Events.topic(&self.config.base_route);
+        EventPublisher::new(self.client.clone(), event_topic)
+    }
+
+    /// Disconnect from the broker and stop all background tasks
+    ///
+    /// Disconnect waits for space in the request queue which never frees up while the broker
+    /// is unreachable, so it is given up on after a short time
+    pub async fn stop(self) {
+        self.router_task.abort();
+        match timeout(DISCONNECT_TIMEOUT, self.client.disconnect()).await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => warn!("Failed to disconnect from MQTT broker {e}"),
+            Err(_) => warn!("MQTT broker didn't take disconnect in time"),
+        }
+        self.event_loop_task.abort();
+    }
+}
+
+/// Connection settings shared by both protocol versions
+struct ConnectionSettings {
+    credentials: Option<(String, String)>,
+    transport: Option<Transport>,
+}
+
+fn connection_settings(config: &MqttConfig) -> Result<ConnectionSettings> {
+    let credentials = match config.username {
+        Some(ref username) => {
+            let password = match (&config.password, &config.password_file) {
+                (Some(password), _) => password.clone(),
+                (None, Some(password_file)) => std::fs::read_to_string(password_file)?
+                    .trim_end()
+                    .to_owned(),
+                (None, None) => String::new(),
+            };
+            info!("Authenticating to MQTT broker as {username}");
+            Some((username.clone(), password))
+        }
+        None => None,
+    };
+
+    let transport = match config.tls {
+        Some(ref tls) => {
+            let ca = std::fs::read(&tls.ca_file)?;
+            let client_auth = match (&tls.client_cert_file, &tls.client_key_file) {
+                (Some(cert_file), Some(key_file)) => {
+                    Some((std::fs::read(cert_file)?, std::fs::read(key_file)?))
+                }
+                (None, None) => None,
+                (_, _) => {
+                    anyhow::bail!("MQTT client certificate and key have to be set together")
+                }
+            };
+            info!("Using TLS for MQTT connection");
+            Some(Transport::tls(ca, client_auth, None))
+        }
+        None => None,
+    };
+
+    Ok(ConnectionSettings {
+        credentials,
+        transport,
+    })
 }
 
 pub fn start_mqtt_service(
     blinds: Arc<Mutex<Box<dyn Blinds>>>,
     config: MqttConfig,
-) -> anyhow::Result<StatePublisher> {
-    let mut mqttoptions =
-        MqttOptions::new(&config.client_id, &config.broker_host, config.broker_port);
-    info!("Starting MQTT server with options {:?}", mqttoptions);
-    mqttoptions.set_keep_alive(Duration::from_secs(5));
+    stats: Arc<MqttConnectionStats>,
+    history: Arc<MotionHistory>,
+) -> anyhow::Result<MqttService> {
+    info!(
+        "Starting MQTT {:?} client with client id {} broker {}:{}",
+        config.protocol, config.client_id, config.broker_host, config.broker_port
+    );
+    let settings = connection_settings(&config)?;
 
-    let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
+    info!("MQTT base topic {}", config.base_route);
 
-    let base_topic = config.base_route;
+    let (message_sender, message_receiver) = unbounded_channel();
 
-    info!("MQTT base topic {}", base_topic);
-
-    let (message_sender, mut message_receiver) = unbounded_channel();
-
-    tokio::spawn(async move {
-        loop {
-            match eventloop.poll().await {
-                Ok(notification) => match notification {
-                    Event::Incoming(Incoming::Publish(publish)) => {
-                        if let Err(e) = message_sender.send(MqttUpdate::Message(publish)) {
-                            eprintln!("Error sending message {}", e);
-                        }
-                    }
-                    Event::Incoming(Incoming::ConnAck(con_ack)) => {
-                        if let Err(e) = message_sender.send(MqttUpdate::Reconnection(con_ack)) {
-                            eprintln!("Error sending message {}", e);
-                        }
-                    }
-                    _ => (),
-                },
-                Err(e) => {
-                    eprintln!("Error processing eventloop notifications {}", e);
-                }
+    let (client, event_loop_task) = match config.protocol {
+        MqttProtocol::V3 => {
+            let mut mqttoptions =
+                MqttOptions::new(&config.client_id, &config.broker_host, config.broker_port);
+            mqttoptions.set_keep_alive(Duration::from_secs(5));
+            if let Some((username, password)) = settings.credentials {
+                mqttoptions.set_credentials(username, password);
+            }
+            if let Some(transport) = settings.transport {
+                mqttoptions.set_transport(transport);
             }
+            let (client, eventloop) = AsyncClient::new(mqttoptions, 10);
+            let task = tokio::spawn(run_event_loop(eventloop, message_sender, stats));
+            (MqttClient::V3(client), task)
         }
-    });
+        MqttProtocol::V5 => {
+            let mut mqttoptions =
+                v5::MqttOptions::new(&config.client_id, &config.broker_host, config.broker_port);
+            mqttoptions.set_keep_alive(Duration::from_secs(5));
+            if let Some((username, password)) = settings.credentials {
+                mqttoptions.set_credentials(username, password);
+            }
+            if let Some(transport) = settings.transport {
+                mqttoptions.set_transport(transport);
+            }
+            let (client, eventloop) = v5::AsyncClient::new(mqttoptions, 10);
+            let task = tokio::spawn(run_event_loop_v5(eventloop, message_sender, stats));
+            (MqttClient::V5(client), task)
+        }
+    };
 
-    tokio::spawn({
+    let router_task = tokio::spawn({
         let client = client.clone();
-        let base_topic = base_topic.clone();
+        let config = config.clone();
         async move {
-            let mut router = Router::default();
-
-            router
-                .add_handler(
-                    &format!("{base_topic}/#"),
-                    BlindsHandler::new(blinds.clone()),
-                )
-                .unwrap();
-
-            if let Some(switch_topic) = config.switch_topic {
-                router
-                    .add_handler(&switch_topic, SwitchHandler::new(blinds))
-                    .unwrap();
+            if let Err(e) = run_router(client, blinds, history, config, message_receiver).await {
+                error!("MQTT router failed. Commands over MQTT are disabled {e}");
             }
+        }
+    });
 
-            let topics = router
-                .topics_for_subscription()
-                .map(|topic| SubscribeFilter {
-                    path: topic.to_owned(),
-                    qos: QoS::AtMostOnce,
-                });
-            client.subscribe_many(topics).await.unwrap();
-
-            loop {
-                let update = message_receiver.recv().await.unwrap();
-                match update {
-                    MqttUpdate::Message(message) => {
-                        match router
-                            .handle_message_ignore_errors(&message.topic, &message.payload)
-                            .await
-                        {
-                            Ok(false) => error!("No handler for topic: \"{}\"", &message.topic),
-                            Ok(true) => (),
-                            Err(e) => error!("Failed running handler with {:?}", e),
-                        }
-                    }
-                    MqttUpdate::Reconnection(_) => {
-                        info!("Reconnecting to broker");
-                        let topics =
-                            router
-                                .topics_for_subscription()
-                                .map(|topic| SubscribeFilter {
-                                    path: topic.to_owned(),
-                                    qos: QoS::AtMostOnce,
-                                });
-                        client.subscribe_many(topics).await.unwrap();
-                    }
+    Ok(MqttService {
+        client,
+        config,
+        event_loop_task,
+        router_task,
+    })
+}
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Exponential backoff with equal jitter
+pub struct Backoff {
+    next_delay: Duration,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self {
+            next_delay: MIN_RECONNECT_DELAY,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.next_delay = MIN_RECONNECT_DELAY;
+    }
+
+    pub fn next(&mut self) -> Duration {
+        let delay = self.next_delay;
+        self.next_delay = (self.next_delay * 2).min(MAX_RECONNECT_DELAY);
+        delay / 2 + delay.mul_f64(rand::thread_rng().gen_range(0.0..0.5))
+    }
+}
+
+async fn run_event_loop(
+    mut eventloop: EventLoop,
+    message_sender: UnboundedSender<MqttUpdate>,
+    stats: Arc<MqttConnectionStats>,
+) {
+    let mut backoff = Backoff::new();
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Incoming::Publish(publish))) => {
+                let message = IncomingMessage {
+                    topic: publish.topic,
+                    payload: publish.payload,
+                    properties: MessageProperties::default(),
+                };
+                if let Err(e) = message_sender.send(MqttUpdate::Message(message)) {
+                    error!("Failed to pass MQTT message to router {e}");
+                }
+            }
+            Ok(Event::Incoming(Incoming::ConnAck(_))) => {
+                info!("Connected to MQTT broker");
+                backoff.reset();
+                stats.set_connected();
+                if let Err(e) = message_sender.send(MqttUpdate::Reconnection) {
+                    error!("Failed to pass MQTT reconnection to router {e}");
                 }
             }
+            Ok(_) => (),
+            Err(e) => {
+                stats.set_disconnected(&e.to_string());
+                let delay = backoff.next();
+                warn!(
+                    "MQTT connection error {e}. Reconnecting in {}ms",
+                    delay.as_millis()
+                );
+                sleep(delay).await;
+            }
         }
-    });
+    }
+}
+
+async fn run_event_loop_v5(
+    mut eventloop: v5::EventLoop,
+    message_sender: UnboundedSender<MqttUpdate>,
+    stats: Arc<MqttConnectionStats>,
+) {
+    let mut backoff = Backoff::new();
+    loop {
+        match eventloop.poll().await {
+            Ok(v5::Event::Incoming(Packet::Publish(publish))) => {
+                let message = IncomingMessage {
+                    topic: String::from_utf8_lossy(&publish.topic).into_owned(),
+                    properties: MessageProperties::from_v5(publish.properties.as_ref()),
+                    payload: publish.payload,
+                };
+                if let Err(e) = message_sender.send(MqttUpdate::Message(message)) {
+                    error!("Failed to pass MQTT message to router {e}");
+                }
+            }
+            Ok(v5::Event::Incoming(Packet::ConnAck(_))) => {
+                info!("Connected to MQTT broker over v5");
+                backoff.reset();
+                stats.set_connected();
+                if let Err(e) = message_sender.send(MqttUpdate::Reconnection) {
+                    error!("Failed to pass MQTT reconnection to router {e}");
+                }
+            }
+            Ok(_) => (),
+            Err(e) => {
+                stats.set_disconnected(&e.to_string());
+                let delay = backoff.next();
+                warn!(
+                    "MQTT connection error {e}. Reconnecting in {}ms",
+                    delay.as_millis()
+                );
+                sleep(delay).await;
+            }
+        }
+    }
+}
+
+async fn run_router(
+    client: MqttClient,
+    blinds: Arc<Mutex<Box<dyn Blinds>>>,
+    history: Arc<MotionHistory>,
+    config: MqttConfig,
+    mut message_receiver: UnboundedReceiver<MqttUpdate>,
+) -> Result<()> {
+    let base_topic = config.base_route;
+    let mut router = Router::default();
+    let responder =
+        CommandResponder::new(client.clone(), &base_topic, config.response_topic_prefix);
+
+    for command in BlindsTopic::COMMANDS {
+        router.add_handler(
+            &command.topic(&base_topic),
+            BlindsHandler::new(
+                blinds.clone(),
+                history.clone(),
+                responder.clone(),
+                base_topic.clone(),
+            ),
+        )?;
+    }
+
+    if let Some(switch_topic) = config.switch_topic {
+        router.add_handler(
+            &switch_topic,
+            SwitchHandler::new(blinds, history, responder.clone(), config.switch_bindings),
+        )?;
+    }
 
-    let update_topic = format!("{}/state", base_topic);
-    let update_service = StatePublisher::new(client, update_topic);
-    Ok(update_service)
+    // subscriptions are made on every ConnAck including the first one
+    while let Some(update) = message_receiver.recv().await {
+        match update {
+            MqttUpdate::Message(message) => {
+                responder.set_message_properties(message.properties);
+                match router
+                    .handle_message_ignore_errors(&message.topic, &message.payload)
+                    .await
+                {
+                    Ok(false) => error!("No handler for topic: \"{}\"", &message.topic),
+                    Ok(true) => (),
+                    Err(e) => error!("Failed running handler with {:?}", e),
+                }
+            }
+            MqttUpdate::Reconnection => {
+                info!("Subscribing to topics");
+                let topics = router
+                    .topics_for_subscription()
+                    .map(|topic| topic.to_owned());
+                if let Err(e) = client.subscribe_many(topics).await {
+                    error!("Failed to subscribe to topics {e}");
+                }
+            }
+        }
+    }
+    warn!("MQTT event loop stopped. Stopping router");
+    Ok(())
+}
+
+/// MQTT connection state shared with the rest of the service
+#[derive(Debug, Default)]
+pub struct MqttConnectionStats {
+    connected: AtomicBool,
+    /// First connection isn't counted as reconnect
+    was_connected: AtomicBool,
+    reconnects: AtomicU64,
+    connection_errors: AtomicU64,
+    last_error: std::sync::Mutex<Option<String>>,
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
-struct StateUpdate {
-    state: BlindsState,
+pub struct MqttConnectionStatus {
+    pub connected: bool,
+    pub reconnects: u64,
+    pub connection_errors: u64,
+    pub last_error: Option<String>,
 }
 
-pub struct StatePublisher {
-    mqtt: AsyncClient,
+impl MqttConnectionStats {
+    fn set_connected(&self) {
+        if !self.connected.swap(true, Ordering::Relaxed) {
+            if self.was_connected.swap(true, Ordering::Relaxed) {
+                self.reconnects.fetch_add(1, Ordering::Relaxed);
+                metrics::record_mqtt_reconnect();
+            }
+            metrics::record_mqtt_connected();
+        }
+    }
+
+    fn set_disconnected(&self, error: &str) {
+        self.connected.store(false, Ordering::Relaxed);
+        metrics::record_mqtt_disconnected();
+        self.connection_errors.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().unwrap() = Some(error.to_owned());
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    pub fn status(&self) -> MqttConnectionStatus {
+        MqttConnectionStatus {
+            connected: self.is_connected(),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            connection_errors: self.connection_errors.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().unwrap().clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct StateTopics {
+    mqtt: MqttClient,
     update_topic: String,
+    status_topic: String,
+}
+
+/// Keeps status of [`StatusModel`] up to date and publishes it when MQTT is running
+#[derive(Clone)]
+pub struct StatePublisher {
+    topics: Option<StateTopics>,
+    status: Arc<StatusModel>,
 }
 
 impl StatePublisher {
-    pub fn new(mqtt: AsyncClient, update_topic: String) -> Self {
-        Self { mqtt, update_topic }
+    pub fn new(
+        mqtt: MqttClient,
+        update_topic: String,
+        status_topic: String,
+        status: Arc<StatusModel>,
+    ) -> Self {
+        Self {
+            topics: Some(StateTopics {
+                mqtt,
+                update_topic,
+                status_topic,
+            }),
+            status,
+        }
     }
 
-    pub async fn update_state(&self, new_state: BlindsState) -> Result<()> {
-        let update = StateUpdate { state: new_state };
-        let json = serde_json::to_vec(&update).unwrap();
-        self.mqtt
-            .publish(&self.update_topic, QoS::AtMostOnce, false, json)
-            .await?;
+    /// Only updates status model. Used while MQTT isn't running
+    pub fn local(status: Arc<StatusModel>) -> Self {
+        Self {
+            topics: None,
+            status,
+        }
+    }
+
+    /// Queue legacy state update and status without waiting
+    ///
+    /// Fails instead of blocking when the broker is unreachable and the request queue is full
+    pub fn publish(&self, blinds: &dyn Blinds) -> Result<()> {
+        let status = self.status.update(blinds);
+        if let Some(ref topics) = self.topics {
+            let update = StateUpdate {
+                state: status.state,
+                preset: status.preset.clone(),
+            };
+            topics
+                .mqtt
+                .try_publish(&topics.update_topic, serde_json::to_vec(&update)?)?;
+        }
+        self.publish_status(&status)
+    }
+
+    /// Queue status without waiting. Legacy state topic is left alone
+    pub fn publish_status(&self, status: &BlindsStatus) -> Result<()> {
+        if let Some(ref topics) = self.topics {
+            topics
+                .mqtt
+                .try_publish(&topics.status_topic, serde_json::to_vec(status)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Publishes every finished motion
+pub struct EventPublisher {
+    mqtt: MqttClient,
+    event_topic: String,
+}
+
+impl EventPublisher {
+    pub fn new(mqtt: MqttClient, event_topic: String) -> Self {
+        Self { mqtt, event_topic }
+    }
+
+    /// Queue motion event without waiting
+    pub fn publish_motion(&self, record: &MotionRecord) -> Result<()> {
+        let json = serde_json::to_vec(record)?;
+        self.mqtt.try_publish(&self.event_topic, json)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn first_connection_is_not_reconnect() {
+        let stats = MqttConnectionStats::default();
+        stats.set_disconnected("connection refused");
+        stats.set_connected();
+        assert_eq!(stats.status().reconnects, 0);
+        stats.set_disconnected("connection reset");
+        stats.set_connected();
+        let status = stats.status();
+        assert!(status.connected);
+        assert_eq!(status.reconnects, 1);
+        assert_eq!(status.connection_errors, 2);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{
+    config::{BlindsConfig, ConfigOverrides, MqttConfig},
+    driver::Blinds,
+    history::MotionHistory,
+    logging::{self, LogLevels},
+    mqtt_server::{start_mqtt_service, MqttConnectionStats, MqttService, StatePublisher},
+};
+use anyhow::Result;
+use log::*;
+use std::{path::PathBuf, sync::Arc};
+use tokio::sync::Mutex;
+
+/// Reloads config from disk and applies it to the running service
+pub struct ConfigReloader {
+    config_path: PathBuf,
+    overrides: ConfigOverrides,
+    blinds: Arc<Mutex<Box<dyn Blinds>>>,
+    mqtt_service: Mutex<Option<MqttService>>,
+    mqtt_stats: Arc<MqttConnectionStats>,
+    history: Arc<MotionHistory>,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        config_path: PathBuf,
+        overrides: ConfigOverrides,
+        blinds: Arc<Mutex<Box<dyn Blinds>>>,
+        mqtt_stats: Arc<MqttConnectionStats>,
+        history: Arc<MotionHistory>,
+    ) -> Self {
+        Self {
+            config_path,
+            overrides,
+            blinds,
+            mqtt_service: Mutex::new(None),
+            mqtt_stats,
+            history,
+        }
+    }
+
+    /// Connect to MQTT broker of config loaded on start
+    ///
+    /// Service keeps running without MQTT when this fails. Next reload tries again
+    pub async fn start_mqtt(&self, mqtt_config: MqttConfig) -> Result<()> {
+        let mut mqtt_service = self.mqtt_service.lock().await;
+        *mqtt_service = Some(self.spawn_mqtt_service(mqtt_config).await?);
+        Ok(())
+    }
+
+    /// Start MQTT service and hand its publishers to driver and history
+    async fn spawn_mqtt_service(&self, mqtt_config: MqttConfig) -> Result<MqttService> {
+        let service = start_mqtt_service(
+            self.blinds.clone(),
+            mqtt_config,
+            self.mqtt_stats.clone(),
+            self.history.clone(),
+        )?;
+        let state_publisher = service.state_publisher(self.history.status_model());
+        self.blinds
+            .lock()
+            .await
+            .set_state_publisher(state_publisher.clone());
+        self.history.set_state_publisher(state_publisher);
+        self.history.set_event_publisher(service.event_publisher());
+        Ok(service)
+    }
+
+    pub async fn reload(&self) -> Result<()> {
+        info!("Reloading config from {:?}", self.config_path);
+        let mut config = BlindsConfig::load(&self.config_path).await?;
+        config.apply_overrides(&self.overrides);
+        let mqtt_config = config.mqtt_config()?;
+        let log_levels = LogLevels::from_config(&config, &self.overrides)?;
+
+        self.blinds.lock().await.update_config(&config)?;
+
+        let mut mqtt_service = self.mqtt_service.lock().await;
+        let mqtt_changed = mqtt_service
+            .as_ref()
+            .map(|service| service.config() != &mqtt_config)
+            .unwrap_or(true);
+        if mqtt_changed {
+            info!("MQTT config changed. Reconnecting");
+            if let Some(old_service) = mqtt_service.take() {
+                old_service.stop().await;
+                // keep `GET /state` current if new service doesn't start
+                let state_publisher = StatePublisher::local(self.history.status_model());
+                self.blinds
+                    .lock()
+                    .await
+                    .set_state_publisher(state_publisher.clone());
+                self.history.set_state_publisher(state_publisher);
+            }
+            *mqtt_service = Some(self.spawn_mqtt_service(mqtt_config).await?);
+        }
+        logging::set_levels(log_levels);
+        info!("Config reloaded");
+        Ok(())
+    }
+}
+
+/// Reload config every time the process receives SIGHUP
+#[cfg(unix)]
+pub fn reload_on_sighup(reloader: Arc<ConfigReloader>) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP");
+            if let Err(e) = reloader.reload().await {
+                error!("Failed to reload config {e}");
+            }
+        }
+    });
+    Ok(())
+}
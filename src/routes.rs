@@ -1,177 +1,908 @@
-use crate::driver::Blinds;
+use crate::{
+    config::SwitchBindings,
+    driver::Blinds,
+    error::DriverError,
+    history::MotionHistory,
+    logging::{self, LogLevels},
+    metrics::CommandSource,
+    mqtt_client::{MessageProperties, MqttClient},
+};
+use anyhow::Result;
 use async_trait::async_trait;
+pub use blinds_protocol::{BlindsAction, BlindsCommand, CommandError};
+use blinds_protocol::{SwitchAction, SwitchPayload};
+use bytes::Bytes;
 use log::*;
 use mqtt_router::{RouteHandler, RouterError};
-use serde::Deserialize;
+use serde::Serialize;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+/// Topics under the base route
+///
+/// Commands are subscribed to one by one. Output topics are published by this service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlindsTopic {
+    Open,
+    Close,
+    Partial,
+    Toggle,
+    Preset,
+    SetPosition,
+    SetTilt,
+    Command,
+    LogLevel,
+    State,
+    Status,
+    Error,
+    Events,
+}
+
+impl BlindsTopic {
+    pub const COMMANDS: [BlindsTopic; 9] = [
+        BlindsTopic::Open,
+        BlindsTopic::Close,
+        BlindsTopic::Partial,
+        BlindsTopic::Toggle,
+        BlindsTopic::Preset,
+        BlindsTopic::SetPosition,
+        BlindsTopic::SetTilt,
+        BlindsTopic::Command,
+        BlindsTopic::LogLevel,
+    ];
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            BlindsTopic::Open => "open",
+            BlindsTopic::Close => "close",
+            BlindsTopic::Partial => "partial",
+            BlindsTopic::Toggle => "toggle",
+            BlindsTopic::Preset => "preset",
+            BlindsTopic::SetPosition => "set_position",
+            BlindsTopic::SetTilt => "set_tilt",
+            BlindsTopic::Command => "command",
+            BlindsTopic::LogLevel => "log_level",
+            BlindsTopic::State => "state",
+            BlindsTopic::Status => "status",
+            BlindsTopic::Error => "error",
+            BlindsTopic::Events => "events",
+        }
+    }
+
+    pub fn topic(&self, base_topic: &str) -> String {
+        format!("{}/{}", base_topic, self.suffix())
+    }
+
+    /// Parse topic that has to be exactly `{base_topic}/{suffix}`
+    pub fn parse(base_topic: &str, topic: &str) -> Option<Self> {
+        let suffix = topic.strip_prefix(base_topic)?.strip_prefix('/')?;
+        match suffix {
+            "open" => Some(BlindsTopic::Open),
+            "close" => Some(BlindsTopic::Close),
+            "partial" => Some(BlindsTopic::Partial),
+            "toggle" => Some(BlindsTopic::Toggle),
+            "preset" => Some(BlindsTopic::Preset),
+            "set_position" => Some(BlindsTopic::SetPosition),
+            "set_tilt" => Some(BlindsTopic::SetTilt),
+            "command" => Some(BlindsTopic::Command),
+            "log_level" => Some(BlindsTopic::LogLevel),
+            "state" => Some(BlindsTopic::State),
+            "status" => Some(BlindsTopic::Status),
+            "error" => Some(BlindsTopic::Error),
+            "events" => Some(BlindsTopic::Events),
+            _ => None,
+        }
+    }
+
+    /// Topics this service publishes to
+    pub fn is_output(&self) -> bool {
+        matches!(
+            self,
+            BlindsTopic::State | BlindsTopic::Status | BlindsTopic::Error | BlindsTopic::Events
+        )
+    }
+}
+
 pub struct BlindsHandler {
     blinds: Arc<Mutex<Box<dyn Blinds>>>,
+    history: Arc<MotionHistory>,
+    responder: CommandResponder,
+    base_topic: String,
 }
 
 impl BlindsHandler {
-    pub fn new(blinds: Arc<Mutex<Box<dyn Blinds>>>) -> Box<Self> {
-        Box::new(Self { blinds })
+    pub fn new(
+        blinds: Arc<Mutex<Box<dyn Blinds>>>,
+        history: Arc<MotionHistory>,
+        responder: CommandResponder,
+        base_topic: String,
+    ) -> Box<Self> {
+        Box::new(Self {
+            blinds,
+            history,
+            responder,
+            base_topic,
+        })
+    }
+
+    async fn execute(
+        &mut self,
+        topic: BlindsTopic,
+        content: &[u8],
+        response: &mut ResponseTarget,
+    ) -> Result<()> {
+        // response topic from MQTT v5 properties
+        if let Some(response_topic) = response.response_topic.take() {
+            self.responder.check_response_topic(&response_topic)?;
+            response.response_topic = Some(response_topic);
+        }
+        let (action, mode) = match topic {
+            BlindsTopic::Open => {
+                info!("Opening blinds");
+                (BlindsAction::Open, None)
+            }
+            BlindsTopic::Close => {
+                info!("Closing blinds");
+                (BlindsAction::Close, None)
+            }
+            BlindsTopic::Partial => {
+                info!("Opening blinds partially");
+                let message_content = std::str::from_utf8(content)?;
+                let open = message_content.trim().parse::<f32>()?;
+                (BlindsAction::Partial { open }, None)
+            }
+            BlindsTopic::Toggle => {
+                info!("Toggling blinds");
+                (BlindsAction::Toggle, None)
+            }
+            BlindsTopic::Preset => {
+                let name = std::str::from_utf8(content)?.trim().to_owned();
+                (BlindsAction::Preset { name }, None)
+            }
+            BlindsTopic::SetPosition => {
+                let position = std::str::from_utf8(content)?.trim().parse::<f32>()?;
+                (BlindsAction::SetPosition { position }, None)
+            }
+            BlindsTopic::SetTilt => {
+                let tilt = std::str::from_utf8(content)?.trim().parse::<f32>()?;
+                (BlindsAction::SetTilt { tilt }, None)
+            }
+            BlindsTopic::Command => {
+                let blinds_command: BlindsCommand = serde_json::from_slice(content)?;
+                if blinds_command.correlation_id.is_some() {
+                    response.correlation_id = blinds_command.correlation_id;
+                }
+                if let Some(response_topic) = blinds_command.response_topic {
+                    self.responder.check_response_topic(&response_topic)?;
+                    response.response_topic = Some(response_topic);
+                }
+                (blinds_command.action, blinds_command.mode)
+            }
+            BlindsTopic::LogLevel => {
+                let levels: LogLevels = serde_json::from_slice(content)?;
+                logging::set_levels(levels);
+                return Ok(());
+            }
+            BlindsTopic::State | BlindsTopic::Status | BlindsTopic::Error | BlindsTopic::Events => {
+                return Ok(())
+            }
+        };
+        let mut blinds = self.blinds.lock().await;
+        blinds.set_motion_mode(mode);
+        let result = self
+            .history
+            .run(CommandSource::Mqtt, &action, blinds.as_mut())
+            .await;
+        blinds.set_motion_mode(None);
+        result
     }
 }
 
 #[async_trait]
 impl RouteHandler for BlindsHandler {
     async fn call(&mut self, topic: &str, content: &[u8]) -> std::result::Result<(), RouterError> {
-        info!("got mqtt message on {topic}");
-        if topic.ends_with("open") {
-            info!("Opening blinds");
-            self.blinds
-                .lock()
-                .await
-                .open()
-                .await
-                .map_err(|e| RouterError::HandlerError(e.into()))?;
-        } else if topic.ends_with("close") {
-            info!("Closing blinds");
-            self.blinds
-                .lock()
-                .await
-                .close()
-                .await
-                .map_err(|e| RouterError::HandlerError(e.into()))?;
-        } else if topic.ends_with("partial") {
-            info!("Opening blinds partially");
-            let message_content =
-                std::str::from_utf8(content).map_err(|e| RouterError::HandlerError(e.into()))?;
-            let open = message_content
-                .parse::<f32>()
-                .map_err(|e| RouterError::HandlerError(e.into()))?;
-            self.blinds
-                .lock()
-                .await
-                .partial_open(open)
-                .await
-                .map_err(|e| RouterError::HandlerError(e.into()))?;
-        } else if topic.ends_with("toggle") {
-            info!("Toggling blinds");
-            self.blinds
-                .lock()
-                .await
-                .toggle()
-                .await
-                .map_err(|e| RouterError::HandlerError(e.into()))?;
-        } else if topic.ends_with("command") {
-            let blinds_command: BlindsCommand = serde_json::from_slice(content)
-                .map_err(|err| RouterError::HandlerError(err.into()))?;
-            match blinds_command.action {
-                BlindsAction::Close => {
-                    self.blinds
-                        .lock()
-                        .await
-                        .close()
-                        .await
-                        .map_err(|e| RouterError::HandlerError(e.into()))?;
-                }
-                BlindsAction::Open => {
-                    self.blinds
-                        .lock()
-                        .await
-                        .open()
-                        .await
-                        .map_err(|e| RouterError::HandlerError(e.into()))?;
-                }
-                BlindsAction::Toggle => {
-                    self.blinds
-                        .lock()
-                        .await
-                        .toggle()
-                        .await
-                        .map_err(|e| RouterError::HandlerError(e.into()))?;
-                }
-                BlindsAction::Partial { open } => {
-                    self.blinds
-                        .lock()
-                        .await
-                        .partial_open(open)
-                        .await
-                        .map_err(|e| RouterError::HandlerError(e.into()))?;
-                }
+        let blinds_topic = match BlindsTopic::parse(&self.base_topic, topic) {
+            Some(blinds_topic) if blinds_topic.is_output() => {
+                debug!("Ignoring message on own output topic {topic}");
+                return Ok(());
             }
-        } else {
-            error!("Unmatched path handler {topic}");
+            Some(blinds_topic) => blinds_topic,
+            None => {
+                error!("Unmatched path handler {topic}");
+                return Ok(());
+            }
+        };
+        let properties = self.responder.message_properties();
+        match properties.source {
+            Some(ref source) => info!("got mqtt message on {topic} from {source}"),
+            None => info!("got mqtt message on {topic}"),
         }
-        Ok(())
+        let mut response = ResponseTarget::from_properties(properties);
+        let result = self.execute(blinds_topic, content, &mut response).await;
+        self.responder.report(topic, &response, &result);
+        result.map_err(|e| RouterError::HandlerError(e.into()))
     }
 }
 
 pub struct SwitchHandler {
     blinds: Arc<Mutex<Box<dyn Blinds>>>,
+    history: Arc<MotionHistory>,
+    responder: CommandResponder,
+    bindings: SwitchBindings,
 }
 
 impl SwitchHandler {
-    pub fn new(blinds: Arc<Mutex<Box<dyn Blinds>>>) -> Box<Self> {
-        Box::new(Self { blinds })
+    pub fn new(
+        blinds: Arc<Mutex<Box<dyn Blinds>>>,
+        history: Arc<MotionHistory>,
+        responder: CommandResponder,
+        bindings: SwitchBindings,
+    ) -> Box<Self> {
+        Box::new(Self {
+            blinds,
+            history,
+            responder,
+            bindings,
+        })
+    }
+
+    async fn execute(&mut self, content: &[u8]) -> Result<()> {
+        let switch_data: SwitchPayload = serde_json::from_slice(content)?;
+
+        let binding = match switch_data.action {
+            SwitchAction::Single => &self.bindings.single,
+            SwitchAction::Double => &self.bindings.double,
+            SwitchAction::Long => &self.bindings.long,
+            SwitchAction::Unknown => {
+                debug!("Ignoring switch event without binding");
+                return Ok(());
+            }
+        };
+        match binding {
+            Some(action) => {
+                info!("Switch {:?} click running {:?}", switch_data.action, action);
+                let mut blinds = self.blinds.lock().await;
+                self.history
+                    .run(CommandSource::Switch, action, blinds.as_mut())
+                    .await?;
+            }
+            None => warn!("No binding for {:?} click", switch_data.action),
+        }
+        Ok(())
     }
 }
 
 #[async_trait]
 impl RouteHandler for SwitchHandler {
-    async fn call(&mut self, _topic: &str, content: &[u8]) -> std::result::Result<(), RouterError> {
+    async fn call(&mut self, topic: &str, content: &[u8]) -> std::result::Result<(), RouterError> {
         info!("Handling switch data");
-        let switch_data: SwitchPayload =
-            serde_json::from_slice(content).map_err(|err| RouterError::HandlerError(err.into()))?;
+        let result = self.execute(content).await;
+        self.responder
+            .report(topic, &ResponseTarget::default(), &result);
+        result.map_err(|e| RouterError::HandlerError(e.into()))
+    }
+}
 
-        match switch_data.action {
-            Action::Single => {
-                info!("Closing blinds");
-                self.blinds
-                    .lock()
-                    .await
-                    .close()
-                    .await
-                    .map_err(|e| RouterError::HandlerError(e.into()))?;
-            }
-            Action::Long => {
-                info!("Opening blinds");
-                self.blinds
-                    .lock()
-                    .await
-                    .open()
-                    .await
-                    .map_err(|e| RouterError::HandlerError(e.into()))?;
-            }
-            Action::Double => warn!("Double click not supported"),
+/// Where to send result of a command
+#[derive(Debug, Default)]
+pub struct ResponseTarget {
+    pub correlation_id: Option<String>,
+    pub response_topic: Option<String>,
+    /// Raw MQTT v5 correlation data echoed back in response properties
+    pub correlation_data: Option<Bytes>,
+}
+
+impl ResponseTarget {
+    fn from_properties(properties: MessageProperties) -> Self {
+        Self {
+            correlation_id: properties
+                .correlation_data
+                .as_ref()
+                .map(|data| String::from_utf8_lossy(data).into_owned()),
+            response_topic: properties.response_topic,
+            correlation_data: properties.correlation_data,
         }
-        Ok(())
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum Action {
-    Single,
-    Double,
-    Long,
+#[derive(Debug, Serialize)]
+pub struct CommandResult {
+    pub topic: String,
+    pub correlation_id: Option<String>,
+    pub success: bool,
+    pub error: Option<CommandError>,
+}
+
+/// Error reported in command results, history and status
+pub fn command_error(error: &anyhow::Error) -> CommandError {
+    let kind = if let Some(driver_error) = error.downcast_ref::<DriverError>() {
+        driver_error.kind()
+    } else if error.is::<serde_json::Error>()
+        || error.is::<std::num::ParseFloatError>()
+        || error.is::<std::str::Utf8Error>()
+    {
+        "InvalidPayload"
+    } else {
+        "Other"
+    };
+    CommandError {
+        kind: kind.to_owned(),
+        message: error.to_string(),
+    }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct SwitchPayload {
-    pub action: Action,
-    #[allow(dead_code)]
-    pub battery: f32,
-    #[allow(dead_code)]
-    pub linkquality: f32,
-    #[allow(dead_code)]
-    pub voltage: f32,
+/// Publishes command results and errors
+#[derive(Clone)]
+pub struct CommandResponder {
+    client: MqttClient,
+    base_topic: String,
+    error_topic: String,
+    response_topic_prefix: Option<String>,
+    /// Properties of message currently being handled
+    ///
+    /// Router handles messages one by one so this is set right before dispatch
+    message_properties: Arc<std::sync::Mutex<MessageProperties>>,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum BlindsAction {
-    Open,
-    Close,
-    Toggle,
-    Partial { open: f32 },
+impl CommandResponder {
+    pub fn new(
+        client: MqttClient,
+        base_topic: &str,
+        response_topic_prefix: Option<String>,
+    ) -> Self {
+        Self {
+            client,
+            base_topic: base_topic.to_owned(),
+            error_topic: BlindsTopic::Error.topic(base_topic),
+            response_topic_prefix,
+            message_properties: Default::default(),
+        }
+    }
+
+    /// Response topics are set by whoever sends the command
+    ///
+    /// Publishing results to own topics would feed them back in as commands
+    pub fn check_response_topic(&self, response_topic: &str) -> Result<()> {
+        let own_topic = response_topic == self.base_topic
+            || response_topic
+                .strip_prefix(&self.base_topic)
+                .map(|rest| rest.starts_with('/'))
+                .unwrap_or(false);
+        let outside_prefix = self
+            .response_topic_prefix
+            .as_ref()
+            .map(|prefix| !response_topic.starts_with(prefix.as_str()))
+            .unwrap_or(false);
+        if response_topic.is_empty()
+            || response_topic.contains(['#', '+'])
+            || own_topic
+            || outside_prefix
+        {
+            anyhow::bail!("Response topic {response_topic} not allowed");
+        }
+        Ok(())
+    }
+
+    pub fn set_message_properties(&self, properties: MessageProperties) {
+        *self.message_properties.lock().unwrap() = properties;
+    }
+
+    pub fn message_properties(&self) -> MessageProperties {
+        self.message_properties.lock().unwrap().clone()
+    }
+
+    /// Publish result to requested response topic and errors to error topic
+    pub fn report(&self, topic: &str, response: &ResponseTarget, result: &Result<()>) {
+        let command_result = CommandResult {
+            topic: topic.to_owned(),
+            correlation_id: response.correlation_id.clone(),
+            success: result.is_ok(),
+            error: result.as_ref().err().map(command_error),
+        };
+        let payload = match serde_json::to_vec(&command_result) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize command result {e}");
+                return;
+            }
+        };
+        if let Some(ref response_topic) = response.response_topic {
+            if let Err(e) = self.client.try_publish_response(
+                response_topic,
+                payload.clone(),
+                response.correlation_data.clone(),
+            ) {
+                warn!("Failed to publish command result to {response_topic} {e}");
+            }
+        }
+        if result.is_err() {
+            if let Err(e) = self.client.try_publish(&self.error_topic, payload) {
+                warn!(
+                    "Failed to publish command error to {} {e}",
+                    self.error_topic
+                );
+            }
+        }
+    }
+}
+
+/// Run action on blinds
+pub async fn run_action(action: &BlindsAction, blinds: &mut dyn Blinds) -> Result<()> {
+    match action {
+        BlindsAction::Close => blinds.close().await,
+        BlindsAction::Open => blinds.open().await,
+        BlindsAction::Toggle => blinds.toggle().await,
+        BlindsAction::Partial { open } => blinds.partial_open(*open).await,
+        BlindsAction::Preset { name } => blinds.apply_preset(name).await,
+        BlindsAction::SetPosition { position } => blinds.set_position(*position).await,
+        BlindsAction::SetTilt { tilt } => blinds.set_tilt(*tilt).await,
+        BlindsAction::Calibrate => blinds.calibrate().await,
+    }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct BlindsCommand {
-    pub action: BlindsAction,
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::{
+        config::{BlindsConfig, MotionMode},
+        driver::BlindsState,
+        history::{HistoryFilter, Outcome},
+        mqtt_server::StatePublisher,
+        status::StatusModel,
+    };
+    use mqtt_router::Router;
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    };
+
+    const BASE_TOPIC: &str = "living_room/blinds";
+
+    pub(crate) type CallLog = Arc<std::sync::Mutex<Vec<String>>>;
+
+    /// Serial bus shared with tests so they can pull the adapter
+    #[derive(Default)]
+    pub(crate) struct FakeBus {
+        /// Motors don't answer and reconnecting fails
+        pub disconnected: AtomicBool,
+        /// Motors lose calibration when reconnected
+        pub rebooted: AtomicBool,
+        /// Nobody moves the slats so calibration times out
+        pub unattended: AtomicBool,
+    }
+
+    struct FakeBlinds {
+        calls: CallLog,
+        bus: Arc<FakeBus>,
+        state: BlindsState,
+        motors_rebooted: bool,
+    }
+
+    #[async_trait]
+    impl Blinds for FakeBlinds {
+        async fn open(&mut self) -> Result<()> {
+            self.calls.lock().unwrap().push("open".to_owned());
+            Ok(())
+        }
+
+        async fn partial_open(&mut self, open: f32) -> Result<()> {
+            self.calls.lock().unwrap().push(format!("partial {open}"));
+            Ok(())
+        }
+
+        async fn close(&mut self) -> Result<()> {
+            self.calls.lock().unwrap().push("close".to_owned());
+            Ok(())
+        }
+
+        async fn toggle(&mut self) -> Result<()> {
+            self.calls.lock().unwrap().push("toggle".to_owned());
+            Ok(())
+        }
+
+        async fn apply_preset(&mut self, name: &str) -> Result<()> {
+            self.calls.lock().unwrap().push(format!("preset {name}"));
+            Ok(())
+        }
+
+        async fn set_position(&mut self, position: f32) -> Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("position {position}"));
+            Ok(())
+        }
+
+        async fn set_tilt(&mut self, tilt: f32) -> Result<()> {
+            self.calls.lock().unwrap().push(format!("tilt {tilt}"));
+            Ok(())
+        }
+
+        async fn were_motors_rebooted(&mut self) -> Result<bool> {
+            if self.bus.rebooted.load(Ordering::SeqCst) {
+                self.motors_rebooted = true;
+            }
+            Ok(self.motors_rebooted)
+        }
+
+        async fn check_motors(&mut self) -> Result<()> {
+            if self.bus.disconnected.load(Ordering::SeqCst) {
+                anyhow::bail!("serial port gone");
+            }
+            Ok(())
+        }
+
+        fn reopen_serial_port(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn set_disconnected(&mut self) -> Result<()> {
+            self.state = BlindsState::Disconnected;
+            Ok(())
+        }
+
+        async fn reconnect(&mut self) -> Result<()> {
+            self.calls.lock().unwrap().push("reconnect".to_owned());
+            if self.bus.disconnected.load(Ordering::SeqCst) {
+                anyhow::bail!("serial port gone");
+            }
+            if self.bus.rebooted.load(Ordering::SeqCst) {
+                self.motors_rebooted = true;
+            }
+            self.state = BlindsState::Other;
+            Ok(())
+        }
+
+        async fn calibrate(&mut self) -> Result<()> {
+            self.calls.lock().unwrap().push("calibrate".to_owned());
+            if self.bus.unattended.load(Ordering::SeqCst) {
+                return Err(DriverError::CalibrationNotStarted.into());
+            }
+            Ok(())
+        }
+
+        fn needs_calibration(&self) -> bool {
+            self.motors_rebooted
+        }
+
+        fn max_command_time(&self) -> Duration {
+            Duration::from_secs(1)
+        }
+
+        fn state(&self) -> BlindsState {
+            self.state
+        }
+
+        fn position(&self) -> Option<f32> {
+            None
+        }
+
+        fn preset(&self) -> Option<String> {
+            None
+        }
+
+        fn update_config(&mut self, _config: &BlindsConfig) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_motion_mode(&mut self, mode: Option<MotionMode>) {
+            if let Some(mode) = mode {
+                self.calls.lock().unwrap().push(format!("mode {mode:?}"));
+            }
+        }
+
+        fn set_state_publisher(&mut self, _state_publisher: StatePublisher) {}
+
+        fn publish_state(&self) {}
+    }
+
+    fn test_responder() -> CommandResponder {
+        let (client, _) =
+            rumqttc::AsyncClient::new(rumqttc::MqttOptions::new("test", "localhost", 1883), 10);
+        CommandResponder::new(
+            MqttClient::V3(client),
+            BASE_TOPIC,
+            Some("home/responses/".to_owned()),
+        )
+    }
+
+    fn test_history() -> Arc<MotionHistory> {
+        Arc::new(MotionHistory::new(None, 100).unwrap())
+    }
+
+    fn test_handler(
+        blinds: Arc<Mutex<Box<dyn Blinds>>>,
+        history: Arc<MotionHistory>,
+    ) -> Box<BlindsHandler> {
+        BlindsHandler::new(blinds, history, test_responder(), BASE_TOPIC.to_owned())
+    }
+
+    fn fake_blinds() -> (Arc<Mutex<Box<dyn Blinds>>>, CallLog) {
+        fake_blinds_on_bus(Default::default())
+    }
+
+    pub(crate) fn fake_blinds_on_bus(bus: Arc<FakeBus>) -> (Arc<Mutex<Box<dyn Blinds>>>, CallLog) {
+        let calls = Arc::new(std::sync::Mutex::new(vec![]));
+        let blinds: Box<dyn Blinds> = Box::new(FakeBlinds {
+            calls: calls.clone(),
+            bus,
+            state: BlindsState::Other,
+            motors_rebooted: false,
+        });
+        (Arc::new(Mutex::new(blinds)), calls)
+    }
+
+    fn test_router() -> (Router, CallLog, Arc<MotionHistory>) {
+        let (blinds, calls) = fake_blinds();
+        let history = test_history();
+        let mut router = Router::default();
+        for command in BlindsTopic::COMMANDS {
+            router
+                .add_handler(
+                    &command.topic(BASE_TOPIC),
+                    test_handler(blinds.clone(), history.clone()),
+                )
+                .unwrap();
+        }
+        (router, calls, history)
+    }
+
+    #[test]
+    fn parse_command_topics() {
+        for command in BlindsTopic::COMMANDS {
+            assert_eq!(
+                BlindsTopic::parse(BASE_TOPIC, &command.topic(BASE_TOPIC)),
+                Some(command)
+            );
+            assert!(!command.is_output());
+        }
+    }
+
+    #[test]
+    fn parse_output_topics() {
+        let state = BlindsTopic::parse(BASE_TOPIC, "living_room/blinds/state").unwrap();
+        assert!(state.is_output());
+        let error = BlindsTopic::parse(BASE_TOPIC, "living_room/blinds/error").unwrap();
+        assert!(error.is_output());
+        let events = BlindsTopic::parse(BASE_TOPIC, "living_room/blinds/events").unwrap();
+        assert!(events.is_output());
+        let status = BlindsTopic::parse(BASE_TOPIC, "living_room/blinds/status").unwrap();
+        assert!(status.is_output());
+    }
+
+    #[test]
+    fn parse_rejects_suffix_matches() {
+        assert_eq!(
+            BlindsTopic::parse(BASE_TOPIC, "living_room/blinds/reopen"),
+            None
+        );
+        assert_eq!(
+            BlindsTopic::parse(BASE_TOPIC, "living_room/blindsopen"),
+            None
+        );
+        assert_eq!(
+            BlindsTopic::parse(BASE_TOPIC, "living_room/blinds/x/open"),
+            None
+        );
+        assert_eq!(BlindsTopic::parse(BASE_TOPIC, "bedroom/blinds/open"), None);
+        assert_eq!(BlindsTopic::parse(BASE_TOPIC, "living_room/blinds"), None);
+    }
+
+    #[tokio::test]
+    async fn route_commands() {
+        let (mut router, calls, _) = test_router();
+        let messages: [(&str, &[u8]); 12] = [
+            ("living_room/blinds/open", b""),
+            ("living_room/blinds/close", b""),
+            ("living_room/blinds/partial", b"0.5"),
+            ("living_room/blinds/toggle", b""),
+            ("living_room/blinds/preset", b"movie\n"),
+            ("living_room/blinds/set_position", b"0.75"),
+            ("living_room/blinds/set_tilt", b"-0.5"),
+            (
+                "living_room/blinds/command",
+                br#"{"action": {"partial": {"open": 0.25}}}"#,
+            ),
+            (
+                "living_room/blinds/command",
+                br#"{"action": {"preset": {"name": "privacy"}}}"#,
+            ),
+            (
+                "living_room/blinds/command",
+                br#"{"action": {"set_tilt": {"tilt": 1.0}}}"#,
+            ),
+            (
+                "living_room/blinds/command",
+                br#"{"action": "open", "mode": "quiet"}"#,
+            ),
+            ("living_room/blinds/command", br#"{"action": "calibrate"}"#),
+        ];
+        for (topic, payload) in messages {
+            assert!(router
+                .handle_message_ignore_errors(topic, payload)
+                .await
+                .unwrap());
+        }
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                "open",
+                "close",
+                "partial 0.5",
+                "toggle",
+                "preset movie",
+                "position 0.75",
+                "tilt -0.5",
+                "partial 0.25",
+                "preset privacy",
+                "tilt 1",
+                "mode Quiet",
+                "open",
+                "calibrate",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_and_output_topics_are_not_routed() {
+        let (mut router, calls, _) = test_router();
+        for topic in [
+            "living_room/blinds/reopen",
+            "living_room/blinds/state",
+            "living_room/blinds/error",
+            "living_room/blinds/events",
+        ] {
+            assert!(!router
+                .handle_message_ignore_errors(topic, b"{}")
+                .await
+                .unwrap());
+        }
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn handler_ignores_output_topics() {
+        let (blinds, calls) = fake_blinds();
+        let mut handler = test_handler(blinds, test_history());
+        handler
+            .call("living_room/blinds/state", br#"{"state": "open"}"#)
+            .await
+            .unwrap();
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn switch_runs_bound_actions() {
+        let (blinds, calls) = fake_blinds();
+        let bindings = SwitchBindings {
+            double: Some(BlindsAction::Preset {
+                name: "movie".to_owned(),
+            }),
+            long: None,
+            ..Default::default()
+        };
+        let history = test_history();
+        let mut handler = SwitchHandler::new(blinds, history.clone(), test_responder(), bindings);
+        for action in ["single", "double", "long", "release"] {
+            let payload = format!(
+                r#"{{"action": "{action}", "battery": 100, "linkquality": 50, "voltage": 3000}}"#
+            );
+            handler
+                .call("zigbee2mqtt/switch", payload.as_bytes())
+                .await
+                .unwrap();
+        }
+        assert_eq!(*calls.lock().unwrap(), vec!["close", "preset movie"]);
+        let records = history.query(&HistoryFilter::default());
+        assert_eq!(records.len(), 2);
+        assert!(records
+            .iter()
+            .all(|record| record.source == CommandSource::Switch));
+    }
+
+    #[test]
+    fn response_topics_are_restricted() {
+        let responder = test_responder();
+        assert!(responder
+            .check_response_topic("home/responses/blinds")
+            .is_ok());
+        for topic in [
+            "living_room/blinds",
+            "living_room/blinds/open",
+            "home/responses/#",
+            "other/topic",
+            "",
+        ] {
+            assert!(responder.check_response_topic(topic).is_err(), "{topic}");
+        }
+    }
+
+    #[tokio::test]
+    async fn commands_with_own_response_topic_are_rejected() {
+        let (mut router, calls, _) = test_router();
+        let _ = router
+            .handle_message_ignore_errors(
+                "living_room/blinds/command",
+                br#"{"action": "open", "response_topic": "living_room/blinds/close"}"#,
+            )
+            .await;
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn commands_are_recorded_in_history() {
+        let (mut router, _, history) = test_router();
+        let messages: [(&str, &[u8]); 3] = [
+            ("living_room/blinds/open", b""),
+            ("living_room/blinds/partial", b"not a number"),
+            (
+                "living_room/blinds/command",
+                br#"{"action": {"set_tilt": {"tilt": 1.0}}}"#,
+            ),
+        ];
+        for (topic, payload) in messages {
+            // invalid payload is reported as handler error
+            let _ = router.handle_message_ignore_errors(topic, payload).await;
+        }
+        let records = history.query(&HistoryFilter::default());
+        // payloads that can't be parsed never move the blinds
+        let actions: Vec<_> = records.iter().map(|record| record.action.clone()).collect();
+        assert_eq!(
+            actions,
+            vec![BlindsAction::Open, BlindsAction::SetTilt { tilt: 1.0 }]
+        );
+        assert!(records.iter().all(
+            |record| record.source == CommandSource::Mqtt && record.outcome == Outcome::Success
+        ));
+    }
+
+    #[tokio::test]
+    async fn status_tracks_last_command() {
+        let (mut router, _, history) = test_router();
+        assert!(router
+            .handle_message_ignore_errors("living_room/blinds/partial", b"0.3")
+            .await
+            .unwrap());
+        let (blinds, _) = fake_blinds();
+        let blinds = blinds.lock().await;
+        let status_model = history.status_model();
+        let status = status_model.status(blinds.as_ref());
+        assert_eq!(status.last_command_source, Some(CommandSource::Mqtt));
+        assert_eq!(status.target_position, None);
+        assert_eq!(status.last_error, None);
+
+        status_model.command_started(CommandSource::Http, &BlindsAction::Close);
+        let status = status_model.status(blinds.as_ref());
+        assert_eq!(status.last_command_source, Some(CommandSource::Http));
+        assert_eq!(status.target_position, Some(0.0));
+
+        status_model.command_finished(&Err(DriverError::SlatsNotOpen.into()));
+        let status = status_model.status(blinds.as_ref());
+        assert_eq!(status.target_position, None);
+        assert_eq!(status.last_error.unwrap().kind, "SlatsNotOpen");
+    }
+
+    #[tokio::test]
+    async fn last_status_includes_command_outcome() {
+        let (blinds, _) = fake_blinds();
+        let blinds = blinds.lock().await;
+        let status_model = StatusModel::default();
+        assert!(status_model.last_status().is_none());
+        assert!(status_model.command_finished(&Ok(())).is_none());
+
+        status_model.command_started(CommandSource::Http, &BlindsAction::Open);
+        status_model.update(blinds.as_ref());
+        assert_eq!(
+            status_model.last_status().unwrap().target_position,
+            Some(1.0)
+        );
+
+        let status = status_model
+            .command_finished(&Err(DriverError::SlatsNotOpen.into()))
+            .unwrap();
+        assert_eq!(status.target_position, None);
+        assert_eq!(status.last_command_source, Some(CommandSource::Http));
+        assert_eq!(status.last_error.unwrap().kind, "SlatsNotOpen");
+        assert_eq!(status_model.last_status().unwrap().target_position, None);
+    }
 }
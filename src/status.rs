@@ -0,0 +1,93 @@
+use crate::{
+    driver::Blinds,
+    metrics::CommandSource,
+    routes::{self, BlindsAction, CommandError},
+};
+use anyhow::Result;
+use blinds_protocol::{BlindsStatus, PROTOCOL_VERSION};
+use chrono::Utc;
+use std::sync::Mutex;
+
+#[derive(Debug, Default)]
+struct LastCommand {
+    source: Option<CommandSource>,
+    target_position: Option<f32>,
+    error: Option<CommandError>,
+}
+
+/// State shared by MQTT `status` topic and `GET /state`
+///
+/// Driver knows where the blinds are. This remembers what was last asked of them
+/// and the last status published by the driver
+#[derive(Debug, Default)]
+pub struct StatusModel {
+    last_command: Mutex<LastCommand>,
+    last_status: Mutex<Option<BlindsStatus>>,
+}
+
+impl StatusModel {
+    pub fn command_started(&self, source: CommandSource, action: &BlindsAction) {
+        let mut last_command = self.last_command.lock().unwrap();
+        last_command.source = Some(source);
+        last_command.target_position = target_position(action);
+        last_command.error = None;
+    }
+
+    /// Returns last status updated with outcome of the command
+    pub fn command_finished(&self, result: &Result<()>) -> Option<BlindsStatus> {
+        let mut last_command = self.last_command.lock().unwrap();
+        last_command.target_position = None;
+        last_command.error = result.as_ref().err().map(routes::command_error);
+        let mut last_status = self.last_status.lock().unwrap();
+        let status = last_status.as_mut()?;
+        status.timestamp = Utc::now();
+        status.target_position = None;
+        status.last_command_source = last_command.source;
+        status.last_error = last_command.error.clone();
+        Some(status.clone())
+    }
+
+    /// Build status from driver and keep it for [`StatusModel::last_status`]
+    pub fn update(&self, blinds: &dyn Blinds) -> BlindsStatus {
+        let status = self.status(blinds);
+        *self.last_status.lock().unwrap() = Some(status.clone());
+        status
+    }
+
+    /// Status served by `GET /state` without locking the driver
+    ///
+    /// `None` until driver first publishes its state
+    pub fn last_status(&self) -> Option<BlindsStatus> {
+        self.last_status.lock().unwrap().clone()
+    }
+
+    pub fn status(&self, blinds: &dyn Blinds) -> BlindsStatus {
+        let last_command = self.last_command.lock().unwrap();
+        BlindsStatus {
+            version: PROTOCOL_VERSION,
+            timestamp: Utc::now(),
+            state: blinds.state(),
+            preset: blinds.preset(),
+            position: blinds.position(),
+            tilt: blinds.tilt(),
+            target_position: last_command.target_position,
+            last_command_source: last_command.source,
+            calibrated: !blinds.needs_calibration(),
+            last_error: last_command.error.clone(),
+        }
+    }
+}
+
+/// Position action moves to. Known only for actions that don't depend on current state or config
+fn target_position(action: &BlindsAction) -> Option<f32> {
+    match action {
+        BlindsAction::Open => Some(1.0),
+        BlindsAction::Close => Some(0.0),
+        BlindsAction::Partial { open } => Some(*open),
+        BlindsAction::SetPosition { position } => Some(*position),
+        BlindsAction::Toggle
+        | BlindsAction::Preset { .. }
+        | BlindsAction::SetTilt { .. }
+        | BlindsAction::Calibrate => None,
+    }
+}
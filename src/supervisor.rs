@@ -0,0 +1,175 @@
+use crate::{driver::Blinds, mqtt_server::Backoff};
+use log::*;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{Mutex, Notify},
+    time::{timeout, Instant},
+};
+
+/// Serial bus that doesn't answer in this time is considered lost
+const CONNECTION_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Reopening port and checking whether motors were rebooted
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reopens serial port after USB serial adapter dropped out
+pub struct ConnectionSupervisor {
+    blinds: Arc<Mutex<Box<dyn Blinds>>>,
+    connection_lost: Notify,
+    disconnected_since: std::sync::Mutex<Option<Instant>>,
+}
+
+impl ConnectionSupervisor {
+    pub fn start(blinds: Arc<Mutex<Box<dyn Blinds>>>) -> Arc<Self> {
+        let supervisor = Arc::new(Self {
+            blinds,
+            connection_lost: Notify::new(),
+            disconnected_since: Default::default(),
+        });
+        tokio::spawn(supervisor.clone().run());
+        supervisor
+    }
+
+    /// Report failed serial command
+    ///
+    /// Motors are queried before reconnecting so a single bad reply doesn't reopen the port
+    pub fn connection_lost(&self) {
+        self.connection_lost.notify_one();
+    }
+
+    /// How long reconnecting has been failing
+    pub fn disconnected_for(&self) -> Option<Duration> {
+        self.disconnected_since
+            .lock()
+            .unwrap()
+            .map(|since| since.elapsed())
+    }
+
+    async fn run(self: Arc<Self>) {
+        loop {
+            self.connection_lost.notified().await;
+            {
+                let mut blinds = self.blinds.lock().await;
+                // adapter that was re-enumerated under another name is followed right away
+                if let Err(e) = blinds.reopen_serial_port() {
+                    warn!("Failed to follow moved serial port {e}");
+                }
+                match timeout(CONNECTION_CHECK_TIMEOUT, blinds.check_motors()).await {
+                    Ok(Ok(())) => {
+                        debug!("Motors still answer. Not reconnecting");
+                        continue;
+                    }
+                    Ok(Err(e)) => error!("Lost connection to motors {e}"),
+                    Err(_) => error!("Lost connection to motors. Motors didn't answer in time"),
+                }
+                if let Err(e) = blinds.set_disconnected().await {
+                    warn!("Failed to mark blinds disconnected {e}");
+                }
+                *self.disconnected_since.lock().unwrap() = Some(Instant::now());
+            }
+            self.reconnect().await;
+        }
+    }
+
+    /// Retry until motors answer again
+    ///
+    /// Driver is only locked for each attempt so commands fail fast in between
+    async fn reconnect(&self) {
+        let mut backoff = Backoff::new();
+        loop {
+            tokio::time::sleep(backoff.next()).await;
+            let mut blinds = self.blinds.lock().await;
+            match timeout(RECONNECT_TIMEOUT, blinds.reconnect()).await {
+                Ok(Ok(())) => {
+                    info!("Reconnected to motors");
+                    *self.disconnected_since.lock().unwrap() = None;
+                    return;
+                }
+                Ok(Err(e)) => warn!("Failed to reconnect to motors {e}"),
+                Err(_) => warn!("Failed to reconnect to motors. Motors didn't answer in time"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        driver::BlindsState,
+        routes::test::{fake_blinds_on_bus, FakeBus},
+    };
+    use std::sync::atomic::Ordering;
+
+    fn pull_adapter(bus: &FakeBus) {
+        bus.disconnected.store(true, Ordering::SeqCst);
+    }
+
+    fn plug_adapter(bus: &FakeBus) {
+        bus.disconnected.store(false, Ordering::SeqCst);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connection_lost_while_motors_answer_is_ignored() {
+        let (blinds, _) = fake_blinds_on_bus(Default::default());
+        let supervisor = ConnectionSupervisor::start(blinds.clone());
+        supervisor.connection_lost();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(blinds.lock().await.state(), BlindsState::Other);
+        assert_eq!(supervisor.disconnected_for(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connection_lost_reconnects_once_motors_answer() {
+        let bus = Arc::new(FakeBus::default());
+        let (blinds, _) = fake_blinds_on_bus(bus.clone());
+        let supervisor = ConnectionSupervisor::start(blinds.clone());
+
+        pull_adapter(&bus);
+        supervisor.connection_lost();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(blinds.lock().await.state(), BlindsState::Disconnected);
+        assert!(supervisor.disconnected_for().unwrap() >= Duration::from_secs(9));
+
+        plug_adapter(&bus);
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(blinds.lock().await.state(), BlindsState::Other);
+        assert_eq!(supervisor.disconnected_for(), None);
+        assert!(!blinds.lock().await.needs_calibration());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnect_backs_off() {
+        let bus = Arc::new(FakeBus::default());
+        let (blinds, calls) = fake_blinds_on_bus(bus.clone());
+        let supervisor = ConnectionSupervisor::start(blinds);
+
+        pull_adapter(&bus);
+        supervisor.connection_lost();
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        // delays double from half a second and jitter takes off up to half of each delay,
+        // so the sixth attempt comes between 15.75 and 31.5 seconds
+        let attempts = calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|call| *call == "reconnect")
+            .count();
+        assert!((5..=6).contains(&attempts), "{attempts} attempts");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn motors_rebooted_while_disconnected_need_calibration() {
+        let bus = Arc::new(FakeBus::default());
+        let (blinds, _) = fake_blinds_on_bus(bus.clone());
+        let supervisor = ConnectionSupervisor::start(blinds.clone());
+
+        pull_adapter(&bus);
+        supervisor.connection_lost();
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        bus.rebooted.store(true, Ordering::SeqCst);
+        plug_adapter(&bus);
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(supervisor.disconnected_for(), None);
+        assert!(blinds.lock().await.needs_calibration());
+    }
+}